@@ -0,0 +1,65 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+@group(1) @binding(0)
+var atlas_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var atlas_sampler: sampler;
+
+struct ChunkMaterial {
+    atlas_size: vec2<f32>,
+};
+@group(1) @binding(2)
+var<uniform> material: ChunkMaterial;
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+
+// matches TILE_INSET in meshing/chunk.rs, keeps the neighbouring tiles from bleeding in
+let TILE_INSET: f32 = 0.05;
+// roughly toward the directional light set up in main.rs
+let LIGHT_DIRECTION: vec3<f32> = vec3<f32>(0.0, 0.7071, 0.7071);
+let AMBIENT: f32 = 0.45;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) atlas_tile: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) atlas_tile: vec2<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = mesh_position_local_to_clip(mesh.model, vec4<f32>(vertex.position, 1.0));
+    out.world_normal = mesh_normal_local_to_world(vertex.normal);
+    out.uv = vertex.uv;
+    out.atlas_tile = vertex.atlas_tile;
+    return out;
+}
+
+struct FragmentInput {
+    @location(0) world_normal: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) atlas_tile: vec2<f32>,
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    // the uvs count blocks across the quad, wrap them so the tile repeats once per block
+    // then squeeze them inside the inset rather than clamping, which would smear the tile's edge pixels
+    let tile_uv = TILE_INSET + fract(in.uv) * (1.0 - 2.0 * TILE_INSET);
+    let atlas_uv = (in.atlas_tile + tile_uv) / material.atlas_size;
+    // the wrap jumps between tiles, so mip selection from the derivatives would break along every block edge
+    let color = textureSampleLevel(atlas_texture, atlas_sampler, atlas_uv, 0.0);
+
+    let diffuse = max(dot(normalize(in.world_normal), LIGHT_DIRECTION), 0.0);
+    return vec4<f32>(color.rgb * (AMBIENT + (1.0 - AMBIENT) * diffuse), color.a);
+}
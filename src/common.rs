@@ -1,3 +1,11 @@
 pub mod types;
 
-pub mod voxels;
\ No newline at end of file
+pub mod voxels;
+
+pub mod blocks;
+
+pub mod content;
+
+pub mod storage;
+
+pub mod positions;
\ No newline at end of file
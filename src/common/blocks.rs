@@ -0,0 +1,144 @@
+use bevy::{prelude::*, utils::HashMap};
+use serde::Deserialize;
+
+use super::content::{ContentError, load_ron, load_required};
+use super::types::{UP_FACE, DOWN_FACE, Voxel};
+
+pub const BLOCK_REGISTRY_PATH: &str = "blocks.ron";
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug, Deserialize)]
+#[serde(transparent)]
+pub struct BlockId(pub u16);
+
+/// Atlas tile coordinates (column, row) for each side of a block
+#[derive(Copy, Clone, Deserialize)]
+pub struct BlockTextures {
+    pub top: (u8, u8),
+    pub bottom: (u8, u8),
+    pub side: (u8, u8),
+}
+
+impl BlockTextures {
+    pub fn get_tile(&self, face: u64) -> (u8, u8) {
+        match face {
+            UP_FACE => self.top,
+            DOWN_FACE => self.bottom,
+            _ => self.side,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct BlockDefinition {
+    pub id: BlockId,
+    pub name: String,
+    pub textures: BlockTextures,
+
+    // solid blocks hide the faces next to them and can't be carved or flowed through
+    #[serde(default = "default_solid")]
+    pub solid: bool,
+    // blocks behind a transparent block still show their faces
+    #[serde(default)]
+    pub transparent: bool,
+    #[serde(default)]
+    pub fluid: bool,
+    // gives off light, nothing reads this until there's a lighting pass
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub emissive: bool,
+}
+
+fn default_solid() -> bool { true }
+
+#[derive(Deserialize)]
+struct BlockRegistryFile {
+    atlas_size: (u8, u8),
+    blocks: Vec<BlockDefinition>,
+}
+
+#[derive(Clone)]
+pub struct BlockRegistry {
+    pub atlas_size: (u8, u8),
+    blocks: Vec<Option<BlockDefinition>>,
+    names: HashMap<String, BlockId>,
+}
+
+impl BlockRegistry {
+    pub fn load(path: &str) -> Result<Self, ContentError> {
+        let file: BlockRegistryFile = load_ron(path)?;
+
+        let mut registry = Self {
+            atlas_size: file.atlas_size,
+            blocks: vec![],
+            names: HashMap::new(),
+        };
+
+        for block in file.blocks {
+            let index = block.id.0 as usize;
+            if registry.blocks.len() <= index {
+                registry.blocks.resize(index + 1, None);
+            }
+
+            if registry.blocks[index].is_some() {
+                return Err(ContentError::DuplicateId(block.id));
+            }
+
+            if registry.names.insert(block.name.clone(), block.id).is_some() {
+                return Err(ContentError::DuplicateName(block.name));
+            }
+
+            registry.blocks[index] = Some(block);
+        }
+
+        Ok(registry)
+    }
+
+    pub fn get(&self, id: BlockId) -> Option<&BlockDefinition> {
+        self.blocks.get(id.0 as usize).and_then(|block| block.as_ref())
+    }
+
+    pub fn get_id(&self, name: &str) -> Option<BlockId> {
+        self.names.get(name).copied()
+    }
+
+    /// Looks up a block named in a content file or the inspector, content is resolved once up front
+    /// so a bad name is reported instead of panicking while generating
+    pub fn resolve(&self, name: &str) -> Result<BlockId, ContentError> {
+        self.get_id(name).ok_or_else(|| ContentError::UnknownBlock(name.to_string()))
+    }
+
+    /// Looks up a block that the engine depends on, panicking if the registry doesn't define it
+    pub fn id(&self, name: &str) -> BlockId {
+        match self.get_id(name) {
+            Some(id) => id,
+            None => panic!("block \"{}\" is missing from the block registry", name),
+        }
+    }
+
+    pub fn is_fluid(&self, id: BlockId) -> bool {
+        self.get(id).is_some_and(|block| block.fluid)
+    }
+
+    pub fn is_solid(&self, id: BlockId) -> bool {
+        self.get(id).is_some_and(|block| block.solid)
+    }
+
+    pub fn is_transparent(&self, id: BlockId) -> bool {
+        self.get(id).is_some_and(|block| block.transparent)
+    }
+
+    /// A filled voxel shows its face toward empty voxels, and toward transparent or non-solid blocks other than its own
+    pub fn should_create_face(&self, voxel: Voxel, neighbor: Voxel) -> bool {
+        if !voxel.is_filled() { return false }
+        if !neighbor.is_filled() { return true }
+
+        let neighbor_type = neighbor.block_type();
+        neighbor_type != voxel.block_type() && (self.is_transparent(neighbor_type) || !self.is_solid(neighbor_type))
+    }
+}
+
+impl FromWorld for BlockRegistry {
+    fn from_world(_world: &mut World) -> Self {
+        load_required(BLOCK_REGISTRY_PATH, BlockRegistry::load)
+    }
+}
@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+
+use bevy::asset::FileAssetIo;
+use serde::de::DeserializeOwned;
+
+use super::blocks::BlockId;
+
+/// Everything that can go wrong loading the RON content files
+#[derive(Debug)]
+pub enum ContentError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, ron::Error),
+    DuplicateId(BlockId),
+    DuplicateName(String),
+    UnknownBiome(String),
+    UnknownBlock(String),
+}
+
+/// Resolves a path inside the assets folder the same way the asset server does, so content loads
+/// no matter which directory the game is started from
+pub fn get_asset_path(path: &str) -> PathBuf {
+    FileAssetIo::get_base_path().join("assets").join(path)
+}
+
+/// Reads and parses a RON file from the assets folder
+pub fn load_ron<T: DeserializeOwned>(path: &str) -> Result<T, ContentError> {
+    let path = get_asset_path(path);
+    let contents = std::fs::read_to_string(&path).map_err(|err| ContentError::Io(path.clone(), err))?;
+    ron::from_str(&contents).map_err(|err| ContentError::Parse(path, err))
+}
+
+/// Loads a content file the game can't run without
+pub fn load_required<T>(path: &str, load: impl FnOnce(&str) -> Result<T, ContentError>) -> T {
+    match load(path) {
+        Ok(content) => content,
+        Err(err) => panic!("failed to load {}: {:?}", path, err),
+    }
+}
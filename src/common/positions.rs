@@ -0,0 +1,149 @@
+use std::ops::Add;
+
+use bevy::math::{IVec3, Vec3};
+
+use super::types::*;
+use super::voxels::voxel_helpers;
+
+/// A voxel position in world space
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct WorldPos(pub IVec3);
+
+/// A chunk's coordinates, measured in chunks
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct ChunkPos(pub Vector3Int);
+
+/// A voxel position inside of a single chunk
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct LocalPos(pub VoxelCoords);
+
+impl WorldPos {
+    pub fn new(x: i32, y: i32, z: i32) -> Self { WorldPos(IVec3::new(x, y, z)) }
+
+    /// The voxel containing a point in world space. Floors rather than truncating so that -0.5 is in voxel -1
+    pub fn from_translation(translation: Vec3) -> Self { WorldPos(translation.floor().as_ivec3()) }
+
+    pub fn from_parts(chunk: ChunkPos, local: LocalPos) -> Self {
+        chunk.origin() + IVec3::new(local.0.x as i32, local.0.y as i32, local.0.z as i32)
+    }
+
+    /// The chunk holding this position. Uses floor division so that -1 lands in chunk -1 rather than chunk 0
+    pub fn chunk(self) -> ChunkPos {
+        ChunkPos(Vector3Int {
+            x: self.0.x.div_euclid(CHUNK_DIMENSIONS.x as i32) as i64,
+            y: self.0.y.div_euclid(CHUNK_DIMENSIONS.y as i32) as i64,
+            z: self.0.z.div_euclid(CHUNK_DIMENSIONS.z as i32) as i64,
+        })
+    }
+
+    pub fn local(self) -> LocalPos {
+        LocalPos(VoxelCoords {
+            x: self.0.x.rem_euclid(CHUNK_DIMENSIONS.x as i32) as u16,
+            y: self.0.y.rem_euclid(CHUNK_DIMENSIONS.y as i32) as u16,
+            z: self.0.z.rem_euclid(CHUNK_DIMENSIONS.z as i32) as u16,
+        })
+    }
+
+    pub fn split(self) -> (ChunkPos, LocalPos) { (self.chunk(), self.local()) }
+
+    /// The world space point at the voxel's minimum corner
+    pub fn translation(self) -> Vec3 { self.0.as_vec3() }
+}
+
+impl Add<IVec3> for WorldPos {
+    type Output = Self;
+
+    fn add(self, offset: IVec3) -> Self { WorldPos(self.0 + offset) }
+}
+
+impl From<IVec3> for WorldPos {
+    fn from(position: IVec3) -> Self { WorldPos(position) }
+}
+
+impl From<WorldPos> for IVec3 {
+    fn from(position: WorldPos) -> Self { position.0 }
+}
+
+impl ChunkPos {
+    /// World position of the chunk's (0, 0, 0) voxel
+    pub fn origin(self) -> WorldPos {
+        WorldPos::new(
+            (self.0.x * CHUNK_DIMENSIONS.x) as i32,
+            (self.0.y * CHUNK_DIMENSIONS.y) as i32,
+            (self.0.z * CHUNK_DIMENSIONS.z) as i32,
+        )
+    }
+}
+
+impl From<Vector3Int> for ChunkPos {
+    fn from(coords: Vector3Int) -> Self { ChunkPos(coords) }
+}
+
+impl From<ChunkPos> for Vector3Int {
+    fn from(chunk: ChunkPos) -> Self { chunk.0 }
+}
+
+impl LocalPos {
+    pub fn from_index(index: usize) -> Self { LocalPos(voxel_helpers::get_coords_from_index(index)) }
+    pub fn index(self) -> usize { voxel_helpers::get_index_from_coords(self.0) }
+}
+
+impl From<VoxelCoords> for LocalPos {
+    fn from(coords: VoxelCoords) -> Self { LocalPos(coords) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translation_floors_toward_negative_infinity() {
+        assert_eq!(WorldPos::from_translation(Vec3::new(0.5, 0.0, 0.99)), WorldPos::new(0, 0, 0));
+        assert_eq!(WorldPos::from_translation(Vec3::new(-0.5, -0.01, -1.0)), WorldPos::new(-1, -1, -1));
+        assert_eq!(WorldPos::from_translation(Vec3::new(-16.5, 0.0, 0.0)), WorldPos::new(-17, 0, 0));
+    }
+
+    #[test]
+    fn negative_positions_map_to_negative_chunks() {
+        let width = CHUNK_DIMENSIONS.x as i32;
+        let height = CHUNK_DIMENSIONS.y as i32;
+
+        assert_eq!(WorldPos::new(0, 0, 0).chunk(), ChunkPos(Vector3Int { x: 0, y: 0, z: 0 }));
+        assert_eq!(WorldPos::new(-1, -1, -1).chunk(), ChunkPos(Vector3Int { x: -1, y: -1, z: -1 }));
+        assert_eq!(WorldPos::new(-width, -height, -width).chunk(), ChunkPos(Vector3Int { x: -1, y: -1, z: -1 }));
+        assert_eq!(WorldPos::new(-width - 1, 0, width).chunk(), ChunkPos(Vector3Int { x: -2, y: 0, z: 1 }));
+    }
+
+    #[test]
+    fn negative_positions_wrap_to_the_far_side_of_the_chunk() {
+        let local = WorldPos::new(-1, -1, -1).local();
+        assert_eq!(local.0, VoxelCoords {
+            x: CHUNK_DIMENSIONS.x as u16 - 1,
+            y: CHUNK_DIMENSIONS.y as u16 - 1,
+            z: CHUNK_DIMENSIONS.z as u16 - 1,
+        });
+
+        let local = WorldPos::new(-CHUNK_DIMENSIONS.x as i32, 0, 0).local();
+        assert_eq!(local.0, VoxelCoords { x: 0, y: 0, z: 0 });
+    }
+
+    #[test]
+    fn split_round_trips() {
+        for position in [
+            WorldPos::new(0, 0, 0),
+            WorldPos::new(-1, -1, -1),
+            WorldPos::new(-17, 200, 33),
+            WorldPos::new(15, -129, -16),
+        ] {
+            let (chunk, local) = position.split();
+            assert_eq!(WorldPos::from_parts(chunk, local), position);
+        }
+    }
+
+    #[test]
+    fn chunk_origin_is_the_first_voxel_in_the_chunk() {
+        let chunk = WorldPos::new(-5, -5, -5).chunk();
+        assert_eq!(chunk.origin().chunk(), chunk);
+        assert_eq!(chunk.origin().local().index(), 0);
+    }
+}
@@ -0,0 +1,350 @@
+use bevy::utils::HashMap;
+
+use super::types::*;
+
+// Chunks are split into 16 tall sections so that the sky and deep underground
+// can collapse down to a single uniform state
+pub const SECTION_HEIGHT: usize = if CHUNK_HEIGHT < 16 { CHUNK_HEIGHT } else { 16 };
+pub const SECTION_VOLUME: usize = CHUNK_LAYER_SIZE * SECTION_HEIGHT;
+pub const SECTION_COUNT: usize = CHUNK_HEIGHT / SECTION_HEIGHT;
+
+const _: () = assert!(SECTION_COUNT * SECTION_HEIGHT == CHUNK_HEIGHT, "chunk height must be a multiple of the section height");
+
+/// Voxel storage for a single chunk. Each voxel is stored as a block state (the voxel with its
+/// coordinates stripped) in a per section palette, with bit packed indices into that palette.
+/// Coordinates are restored from the index when reading.
+#[derive(Clone, Default)]
+pub struct VoxelStorage {
+    sections: Vec<VoxelSection>,
+}
+
+#[derive(Clone)]
+enum VoxelSection {
+    Uniform(Voxel),
+    Paletted(PalettedSection),
+}
+
+#[derive(Clone)]
+struct PalettedSection {
+    palette: Vec<Voxel>,
+    // how many voxels point at each palette entry, entries at zero get reused before the palette grows
+    counts: Vec<u16>,
+    bits: u32,
+    data: Vec<u64>,
+}
+
+fn bits_for_palette(len: usize) -> u32 {
+    usize::BITS - (len.max(2) - 1).leading_zeros()
+}
+
+impl PalettedSection {
+    fn with_palette(palette: Vec<Voxel>, counts: Vec<u16>) -> Self {
+        let bits = bits_for_palette(palette.len());
+        let entries_per_word = (64 / bits) as usize;
+        Self {
+            palette,
+            counts,
+            bits,
+            data: vec![0; SECTION_VOLUME.div_ceil(entries_per_word)],
+        }
+    }
+
+    fn get_entry(&self, index: usize) -> usize {
+        let entries_per_word = (64 / self.bits) as usize;
+        let word = self.data[index / entries_per_word];
+        let shift = (index % entries_per_word) as u32 * self.bits;
+        ((word >> shift) & ((1u64 << self.bits) - 1)) as usize
+    }
+
+    fn set_entry(&mut self, index: usize, entry: usize) {
+        let entries_per_word = (64 / self.bits) as usize;
+        let shift = (index % entries_per_word) as u32 * self.bits;
+        let mask = ((1u64 << self.bits) - 1) << shift;
+        let word = &mut self.data[index / entries_per_word];
+        *word = (*word & !mask) | ((entry as u64) << shift);
+    }
+
+    fn get(&self, index: usize) -> Voxel {
+        self.palette[self.get_entry(index)]
+    }
+
+    fn set(&mut self, index: usize, state: Voxel) {
+        let old_entry = self.get_entry(index);
+        if self.palette[old_entry] == state { return }
+        self.counts[old_entry] -= 1;
+
+        let entry = match self.palette.iter().position(|&s| s == state) {
+            Some(entry) => entry,
+            None => match self.counts.iter().position(|&count| count == 0) {
+                Some(entry) => {
+                    self.palette[entry] = state;
+                    entry
+                },
+                None => {
+                    self.palette.push(state);
+                    self.counts.push(0);
+                    if bits_for_palette(self.palette.len()) > self.bits {
+                        self.repack();
+                    }
+                    self.palette.len() - 1
+                }
+            }
+        };
+
+        self.counts[entry] += 1;
+        self.set_entry(index, entry);
+
+        // only shrink once the palette would fit in two fewer bits, so a palette sitting right on a
+        // boundary doesn't repack on every write
+        if bits_for_palette(self.live_entries()) + 1 < self.bits {
+            self.compact();
+        }
+    }
+
+    fn live_entries(&self) -> usize {
+        self.counts.iter().filter(|&&count| count > 0).count()
+    }
+
+    // the state every voxel shares, once only one palette entry is still in use
+    fn uniform_state(&self) -> Option<Voxel> {
+        let mut live = self.counts.iter().enumerate().filter(|(_, &count)| count > 0);
+        match (live.next(), live.next()) {
+            (Some((entry, _)), None) => Some(self.palette[entry]),
+            _ => None,
+        }
+    }
+
+    fn repack(&mut self) {
+        let mut repacked = PalettedSection::with_palette(std::mem::take(&mut self.palette), std::mem::take(&mut self.counts));
+        for index in 0..SECTION_VOLUME {
+            repacked.set_entry(index, self.get_entry(index));
+        }
+
+        *self = repacked;
+    }
+
+    // drops the unused palette entries and packs the indices into as few bits as the rest need
+    fn compact(&mut self) {
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = vec![];
+        let mut counts = vec![];
+        for (entry, (&state, &count)) in self.palette.iter().zip(&self.counts).enumerate() {
+            if count == 0 { continue }
+            remap[entry] = palette.len();
+            palette.push(state);
+            counts.push(count);
+        }
+
+        let mut compacted = PalettedSection::with_palette(palette, counts);
+        for index in 0..SECTION_VOLUME {
+            compacted.set_entry(index, remap[self.get_entry(index)]);
+        }
+
+        *self = compacted;
+    }
+}
+
+impl VoxelSection {
+    fn from_voxels(voxels: &[Voxel]) -> Self {
+        let mut palette = vec![];
+        let mut counts = vec![];
+        let mut lookup = HashMap::<Voxel, usize>::new();
+        let mut entries = Vec::with_capacity(voxels.len());
+
+        for &voxel in voxels {
+            let state = voxel.state();
+            let entry = *lookup.entry(state).or_insert_with(|| {
+                palette.push(state);
+                counts.push(0);
+                palette.len() - 1
+            });
+            counts[entry] += 1;
+            entries.push(entry);
+        }
+
+        if palette.len() == 1 {
+            return VoxelSection::Uniform(palette[0]);
+        }
+
+        let mut section = PalettedSection::with_palette(palette, counts);
+        for (index, entry) in entries.into_iter().enumerate() {
+            section.set_entry(index, entry);
+        }
+
+        VoxelSection::Paletted(section)
+    }
+
+    fn get(&self, index: usize) -> Voxel {
+        match self {
+            VoxelSection::Uniform(state) => *state,
+            VoxelSection::Paletted(section) => section.get(index),
+        }
+    }
+
+    fn set(&mut self, index: usize, state: Voxel) {
+        match self {
+            VoxelSection::Uniform(current) => {
+                if *current == state { return }
+
+                let mut section = PalettedSection::with_palette(vec![*current, state], vec![SECTION_VOLUME as u16 - 1, 1]);
+                section.set_entry(index, 1);
+                *self = VoxelSection::Paletted(section);
+            },
+            VoxelSection::Paletted(section) => {
+                section.set(index, state);
+                if let Some(state) = section.uniform_state() {
+                    *self = VoxelSection::Uniform(state);
+                }
+            },
+        }
+    }
+}
+
+impl VoxelStorage {
+    pub fn from_voxels(voxels: &[Voxel]) -> Self {
+        debug_assert_eq!(voxels.len(), SECTION_COUNT * SECTION_VOLUME, "chunk storage needs a full chunk of voxels");
+        Self {
+            sections: voxels.chunks(SECTION_VOLUME).map(VoxelSection::from_voxels).collect(),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<Voxel> {
+        let section = self.sections.get(index / SECTION_VOLUME)?;
+        let mut voxel = section.get(index % SECTION_VOLUME);
+        voxel.set_index(index);
+        Some(voxel)
+    }
+
+    pub fn set(&mut self, index: usize, voxel: Voxel) -> Option<Voxel> {
+        let section = self.sections.get_mut(index / SECTION_VOLUME)?;
+        section.set(index % SECTION_VOLUME, voxel.state());
+        Some(voxel)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Voxel> + '_ {
+        self.sections.iter().enumerate().flat_map(|(section_index, section)| {
+            let offset = section_index * SECTION_VOLUME;
+            (0..SECTION_VOLUME).map(move |index| {
+                let mut voxel = section.get(index);
+                voxel.set_index(offset + index);
+                voxel
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::blocks::BlockId;
+
+    fn voxel(index: usize, block: u16) -> Voxel {
+        let mut voxel = Voxel::new(index);
+        voxel.set_filled(true);
+        voxel.set_block_type(BlockId(block));
+        voxel
+    }
+
+    fn empty_storage() -> VoxelStorage {
+        let voxels: Vec<Voxel> = (0..CHUNK_VOLUME).map(Voxel::new).collect();
+        VoxelStorage::from_voxels(&voxels)
+    }
+
+    fn section_bits(storage: &VoxelStorage, section: usize) -> Option<u32> {
+        match &storage.sections[section] {
+            VoxelSection::Uniform(_) => None,
+            VoxelSection::Paletted(section) => Some(section.bits),
+        }
+    }
+
+    // writes `blocks` different block types across the first section, one per voxel in turn
+    fn fill_first_section(storage: &mut VoxelStorage, blocks: u16) {
+        for index in 0..SECTION_VOLUME {
+            storage.set(index, voxel(index, index as u16 % blocks));
+        }
+    }
+
+    fn assert_first_section(storage: &VoxelStorage, blocks: u16) {
+        for index in 0..SECTION_VOLUME {
+            assert_eq!(storage.get(index), Some(voxel(index, index as u16 % blocks)), "index {}", index);
+        }
+    }
+
+    #[test]
+    fn every_index_round_trips() {
+        let voxels: Vec<Voxel> = (0..CHUNK_VOLUME).map(|index| voxel(index, (index * 7 % 13) as u16)).collect();
+        let storage = VoxelStorage::from_voxels(&voxels);
+
+        for (index, &expected) in voxels.iter().enumerate() {
+            assert_eq!(storage.get(index), Some(expected));
+        }
+        assert!(storage.iter().eq(voxels.iter().copied()));
+        assert_eq!(storage.get(CHUNK_VOLUME), None);
+    }
+
+    #[test]
+    fn single_state_sections_are_uniform() {
+        let storage = empty_storage();
+        assert!((0..SECTION_COUNT).all(|section| section_bits(&storage, section).is_none()));
+        assert_eq!(storage.get(5), Some(Voxel::new(5)));
+    }
+
+    #[test]
+    fn palette_grows_past_each_size() {
+        let mut storage = empty_storage();
+
+        storage.set(3, voxel(3, 1));
+        assert_eq!(section_bits(&storage, 0), Some(1));
+        assert_eq!(storage.get(3), Some(voxel(3, 1)));
+        assert_eq!(storage.get(4), Some(Voxel::new(4)));
+
+        for (blocks, bits) in [(3, 2), (5, 3), (17, 5)] {
+            fill_first_section(&mut storage, blocks);
+            assert_eq!(section_bits(&storage, 0), Some(bits), "{} blocks", blocks);
+            assert_first_section(&storage, blocks);
+        }
+
+        // the other sections weren't touched
+        assert!((1..SECTION_COUNT).all(|section| section_bits(&storage, section).is_none()));
+    }
+
+    #[test]
+    fn palette_shrinks_back() {
+        let mut storage = empty_storage();
+        fill_first_section(&mut storage, 17);
+        assert_eq!(section_bits(&storage, 0), Some(5));
+
+        fill_first_section(&mut storage, 8);
+        assert_eq!(section_bits(&storage, 0), Some(3));
+        assert_first_section(&storage, 8);
+
+        fill_first_section(&mut storage, 2);
+        assert_eq!(section_bits(&storage, 0), Some(1));
+        assert_first_section(&storage, 2);
+
+        fill_first_section(&mut storage, 1);
+        assert_eq!(section_bits(&storage, 0), None);
+        assert_first_section(&storage, 1);
+    }
+
+    #[test]
+    fn freed_entries_are_reused() {
+        let voxels: Vec<Voxel> = (0..CHUNK_VOLUME).map(|index| voxel(index, index as u16 % 4)).collect();
+        let mut storage = VoxelStorage::from_voxels(&voxels);
+        assert_eq!(section_bits(&storage, 0), Some(2));
+
+        // clear out block 3, then put a new block 9 in its place, it should take block 3's free entry
+        for index in (3..SECTION_VOLUME).step_by(4) {
+            storage.set(index, voxel(index, 0));
+        }
+        for index in (3..SECTION_VOLUME).step_by(4) {
+            storage.set(index, voxel(index, 9));
+        }
+        assert_eq!(section_bits(&storage, 0), Some(2));
+
+        for index in 0..SECTION_VOLUME {
+            let block = if index % 4 == 3 { 9 } else { index as u16 % 4 };
+            assert_eq!(storage.get(index), Some(voxel(index, block)));
+        }
+    }
+}
@@ -1,17 +1,27 @@
 use std::ops::{Add, Sub};
 
-use bevy::{prelude::Entity, utils::HashMap};
+use bevy::{prelude::Entity, utils::HashSet};
 
+use super::blocks::BlockId;
+use super::positions::WorldPos;
+use super::voxels::Facing;
 use super::voxels::voxel_helpers;
+use super::storage::VoxelStorage;
 
-pub type Voxel = u64;
-pub type VoxelCollection = Vec<Voxel>;
+/// A single voxel packed into a u64, see `common::voxels` for the bit layout
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Voxel(pub(crate) u64);
 
-pub const CHUNK_DIMENSIONS : Vector3Int = Vector3Int {x: 16, y: 128, z:16};
+pub type VoxelCollection = Vec<Voxel>;
 
-pub const COORDINATE_SHIFTS: (u16, u16, u16) = (0, 8, 4);
+// Every module reads the chunk size from here. Voxels are indexed x first, then z, then y
+pub const CHUNK_WIDTH: usize = 16;
+pub const CHUNK_HEIGHT: usize = 128;
 
-pub const COORDINATE_MASKS: (u16, u16, u16) = (0xF, 0xFF, 0xF);
+pub const CHUNK_DIMENSIONS : Vector3Int = Vector3Int {x: CHUNK_WIDTH as i64, y: CHUNK_HEIGHT as i64, z: CHUNK_WIDTH as i64};
+pub const CHUNK_LAYER_SIZE: usize = CHUNK_WIDTH * CHUNK_WIDTH;
+pub const CHUNK_VOLUME: usize = CHUNK_LAYER_SIZE * CHUNK_HEIGHT;
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
 pub struct Vector3Int {
@@ -21,7 +31,7 @@ pub struct Vector3Int {
 }
 
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
 pub struct VoxelCoords {
 	pub x: u16,
 	pub y: u16,
@@ -92,21 +102,58 @@ impl Sub for Vector3 {
 }
 
 
+/// A single block of a placed structure, None clears the voxel
+#[derive(Copy, Clone)]
+pub struct StructureBlock {
+    pub position: WorldPos,
+    pub block: Option<BlockId>,
+    // when false the block only goes into empty voxels, so leaves don't cut into the ground
+    pub replace: bool,
+    // which way the block's top points, so logs can lie on their side
+    pub facing: Facing,
+}
+
+impl StructureBlock {
+    pub fn apply(&self, voxel: &mut Voxel) {
+        if !self.replace && voxel.is_filled() { return }
+
+        voxel.set_block_state(0);
+        match self.block {
+            Some(block) => {
+                voxel.set_filled(true);
+                voxel.set_block_type(block);
+                voxel.set_facing(self.facing);
+            },
+            None => {
+                voxel.set_filled(false);
+                voxel.set_block_type(BlockId::default());
+            },
+        }
+    }
+}
 
 pub struct ChunkData {
-    pub voxels: Vec<Voxel>,
+    pub voxels: VoxelStorage,
     pub entity: Option<Entity>,
     pub has_generated_structures: bool,
-    pub flowing_fluids: HashMap<usize, u8>,
+    // every block of the structures this chunk placed, worked out from its own terrain. they're replayed
+    // into the neighbours they reach whenever those are generated
+    pub structure_blocks: Vec<StructureBlock>,
+    // the chunks whose structure blocks are already in this chunk, so each one only goes in once
+    pub applied_structures: HashSet<Vector3Int>,
+    // fluid voxels that still need to spread, their level is kept in the voxel's block state
+    pub fluid_updates: HashSet<usize>,
 }
 
 impl Default for ChunkData {
     fn default() -> Self {
         Self { 
-            voxels: vec!(), 
+            voxels: VoxelStorage::default(), 
             entity: None, 
             has_generated_structures: false, 
-            flowing_fluids: HashMap::<usize, u8>::new()
+            structure_blocks: vec![],
+            applied_structures: HashSet::new(),
+            fluid_updates: HashSet::<usize>::new()
         }
     }
 }
@@ -142,34 +189,3 @@ pub const NOT_LEFT_FACE:        u64 =  !LEFT_FACE;
 pub const NOT_RIGHT_FACE:       u64 =  !RIGHT_FACE;
 pub const NOT_FORWARD_FACE:     u64 =  !FORWARD_FACE;
 pub const NOT_BACKWARD_FACE:    u64 =  !BACKWARD_FACE;
-
-#[derive(Copy, Clone, Eq, PartialEq)]
-pub enum BlockType {
-	Water,
-	Stone,
-	Grass,
-	Dirt,
-	Snow,
-	Sand,
-	Ice,
-	DarkStone,
-}
-
-
-impl TryFrom<u64> for BlockType {
-    type Error = ();
-
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
-		  match value {
-			v if v == BlockType::Water as u64 => Ok(BlockType::Water),
-			v if v == BlockType::Stone as u64 => Ok(BlockType::Stone),
-			v if v == BlockType::Grass as u64 => Ok(BlockType::Grass),
-			v if v == BlockType::Dirt as u64 => Ok(BlockType::Dirt),
-			v if v == BlockType::Snow as u64 => Ok(BlockType::Snow),
-			v if v == BlockType::Sand as u64 => Ok(BlockType::Sand),
-			v if v == BlockType::Ice as u64 => Ok(BlockType::Ice),
-			v if v == BlockType::DarkStone as u64 => Ok(BlockType::DarkStone),
-			_ => Err(())
-		  }
-    }
-}
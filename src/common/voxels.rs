@@ -1,36 +1,175 @@
-pub mod voxel_helpers {
-	const COORDS_OFFSET: u64 = 0u64;
-	const COORDS_LENGTH: u64 = 16u64;
-	const COORDS_MASK: u64 = !0xFFFFu64;
-	const IS_FILLED_OFFSET: u64 = COORDS_OFFSET + COORDS_LENGTH;
-	const IS_FILLED_LENGTH: u64  = 1u64;
-	const IS_FILLED_MASK:u64 = !(1u64 << IS_FILLED_OFFSET);
-	const MESH_DATA_OFFSET: u64 = IS_FILLED_OFFSET + IS_FILLED_LENGTH;
-	const MESH_DATA_LENGTH: u64 = 6u64;
-	const MESH_DATA_MASK: u64 = !(0b111111 << MESH_DATA_OFFSET);
-    const BLOCK_TYPE_OFFSET: u64 = MESH_DATA_OFFSET + MESH_DATA_LENGTH;
-    const BLOCK_TYPE_MASK: u64 = 0xFFFF;
-    const BLOCK_TYPE_CLEAR_MASK: u64 = !(BLOCK_TYPE_MASK << BLOCK_TYPE_OFFSET);
+use crate::common::{types::*, blocks::BlockId};
 
-    #[allow(dead_code)]
-    const BLOCK_TYPE_LENGTH: u64 = 16u64;
+// Voxel bit layout, from the lowest bit up:
+//   coords       enough bits to index CHUNK_VOLUME, 15 for a 16x128x16 chunk
+//   filled        1 bit
+//   mesh data     6 bits   one bit per visible face
+//   block type   16 bits   id in the BlockRegistry
+//   block state  16 bits   per block metadata, see below
+//   extra        whatever is left, 10 bits for a 16x128x16 chunk
+//
+// Block state layout, from the lowest bit up:
+//   facing        3 bits   direction the block's top texture points, see Facing
+//   fluid level   4 bits   how much further a fluid can spread, 0 for still fluids
+//   growth stage  3 bits   for crops and saplings
+//   reserved      6 bits
 
-    use crate::{common::types::*};
+const COORDS_OFFSET: u64 = 0u64;
+const COORDS_LENGTH: u64 = (usize::BITS - (CHUNK_VOLUME - 1).leading_zeros()) as u64;
+const COORDS_MASK: u64 = !((1u64 << COORDS_LENGTH) - 1);
+const IS_FILLED_OFFSET: u64 = COORDS_OFFSET + COORDS_LENGTH;
+const IS_FILLED_LENGTH: u64  = 1u64;
+const IS_FILLED_MASK:u64 = !(1u64 << IS_FILLED_OFFSET);
+const MESH_DATA_OFFSET: u64 = IS_FILLED_OFFSET + IS_FILLED_LENGTH;
+const MESH_DATA_LENGTH: u64 = 6u64;
+const MESH_DATA_MASK: u64 = !(0b111111 << MESH_DATA_OFFSET);
+const BLOCK_TYPE_OFFSET: u64 = MESH_DATA_OFFSET + MESH_DATA_LENGTH;
+const BLOCK_TYPE_MASK: u64 = 0xFFFF;
+const BLOCK_TYPE_CLEAR_MASK: u64 = !(BLOCK_TYPE_MASK << BLOCK_TYPE_OFFSET);
+const BLOCK_TYPE_LENGTH: u64 = 16u64;
+const BLOCK_STATE_OFFSET: u64 = BLOCK_TYPE_OFFSET + BLOCK_TYPE_LENGTH;
+const BLOCK_STATE_LENGTH: u64 = 16u64;
+const BLOCK_STATE_MASK: u64 = 0xFFFF;
+const BLOCK_STATE_CLEAR_MASK: u64 = !(BLOCK_STATE_MASK << BLOCK_STATE_OFFSET);
+const EXTRA_OFFSET: u64 = BLOCK_STATE_OFFSET + BLOCK_STATE_LENGTH;
+const EXTRA_MASK: u64 = u64::MAX >> EXTRA_OFFSET;
+const EXTRA_CLEAR_MASK: u64 = !(EXTRA_MASK << EXTRA_OFFSET);
 
-    // TODO: Convert these into proper into/from methods
-    pub fn get_coords_as_voxel_coords(v: Voxel) -> VoxelCoords {
-        VoxelCoords {
-            x: ((v >> COORDINATE_SHIFTS.0) as u16 & COORDINATE_MASKS.0) as u16,
-            y: ((v >> COORDINATE_SHIFTS.1) as u16 & COORDINATE_MASKS.1) as u16,
-            z: ((v >> COORDINATE_SHIFTS.2) as u16 & COORDINATE_MASKS.2) as u16,
+const _: () = assert!(EXTRA_OFFSET <= 64, "chunk dimensions are too large for the voxel layout");
+
+const FACING_OFFSET: u16 = 0;
+const FACING_MASK: u16 = 0b111;
+const FLUID_LEVEL_OFFSET: u16 = 3;
+const FLUID_LEVEL_MASK: u16 = 0b1111;
+const GROWTH_STAGE_OFFSET: u16 = 7;
+const GROWTH_STAGE_MASK: u16 = 0b111;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Facing {
+    Up,
+    Down,
+    Left,
+    Right,
+    Forward,
+    Backward,
+}
+
+impl Facing {
+    pub fn from_bits(bits: u16) -> Self {
+        match bits {
+            1 => Facing::Down,
+            2 => Facing::Left,
+            3 => Facing::Right,
+            4 => Facing::Forward,
+            5 => Facing::Backward,
+            _ => Facing::Up,
         }
     }
 
-    pub fn get_coords_as_vec3(v: Voxel) -> Vector3 {
-        Vector3 {
-            x: ((v >> COORDINATE_SHIFTS.0) as u16 & COORDINATE_MASKS.0) as f64,
-            y: ((v >> COORDINATE_SHIFTS.1) as u16 & COORDINATE_MASKS.1) as f64,
-            z: ((v >> COORDINATE_SHIFTS.2) as u16 & COORDINATE_MASKS.2) as f64,
+    /// The face of an upright block whose texture ends up on `face` once the block is rotated to this facing
+    pub fn get_texture_face(self, face: u64) -> u64 {
+        let (top, bottom) = match self {
+            Facing::Up => return face,
+            Facing::Down => (DOWN_FACE, UP_FACE),
+            Facing::Left => (LEFT_FACE, RIGHT_FACE),
+            Facing::Right => (RIGHT_FACE, LEFT_FACE),
+            Facing::Forward => (FORWARD_FACE, BACKWARD_FACE),
+            Facing::Backward => (BACKWARD_FACE, FORWARD_FACE),
+        };
+
+        if face == top {
+            UP_FACE
+        } else if face == bottom {
+            DOWN_FACE
+        } else if face == UP_FACE || face == DOWN_FACE {
+            top
+        } else {
+            face
+        }
+    }
+}
+
+fn get_state_field(state: u16, offset: u16, mask: u16) -> u16 { (state >> offset) & mask }
+fn set_state_field(state: u16, offset: u16, mask: u16, value: u16) -> u16 { (state & !(mask << offset)) | ((value & mask) << offset) }
+
+impl Voxel {
+    /// An empty voxel at the given index in the chunk
+    pub fn new(index: usize) -> Self { Voxel(index as u64) }
+
+    pub fn coords(self) -> VoxelCoords { voxel_helpers::get_coords_from_index(self.index()) }
+    pub fn index(self) -> usize { ((self.0 >> COORDS_OFFSET) & !COORDS_MASK) as usize }
+    pub fn set_index(&mut self, index: usize) { self.0 = (self.0 & COORDS_MASK) | ((index as u64) << COORDS_OFFSET) }
+
+    /// The voxel with its coordinates cleared, every voxel of the same block and state shares this
+    pub fn state(self) -> Voxel { Voxel(self.0 & COORDS_MASK) }
+
+    pub fn is_filled(self) -> bool { ((self.0 >> IS_FILLED_OFFSET) & 0b1) == 1 }
+    pub fn set_filled(&mut self, filled: bool) { self.0 = (self.0 & IS_FILLED_MASK) | ((filled as u64) << IS_FILLED_OFFSET) }
+
+    pub fn face_mask(self) -> u64 { (self.0 >> MESH_DATA_OFFSET) & 0b111111 }
+    pub fn set_face_mask(&mut self, mesh_data: u64) { self.0 = (self.0 & MESH_DATA_MASK) | ((mesh_data & 0b111111) << MESH_DATA_OFFSET) }
+
+    pub fn block_type(self) -> BlockId { BlockId(((self.0 >> BLOCK_TYPE_OFFSET) & BLOCK_TYPE_MASK) as u16) }
+    pub fn set_block_type(&mut self, block_type: BlockId) { self.0 = (self.0 & BLOCK_TYPE_CLEAR_MASK) | ((block_type.0 as u64) << BLOCK_TYPE_OFFSET) }
+
+    pub fn block_state(self) -> u16 { ((self.0 >> BLOCK_STATE_OFFSET) & BLOCK_STATE_MASK) as u16 }
+    pub fn set_block_state(&mut self, block_state: u16) { self.0 = (self.0 & BLOCK_STATE_CLEAR_MASK) | ((block_state as u64) << BLOCK_STATE_OFFSET) }
+
+    pub fn facing(self) -> Facing { Facing::from_bits(get_state_field(self.block_state(), FACING_OFFSET, FACING_MASK)) }
+    pub fn set_facing(&mut self, facing: Facing) {
+        self.set_block_state(set_state_field(self.block_state(), FACING_OFFSET, FACING_MASK, facing as u16))
+    }
+
+    pub fn fluid_level(self) -> u8 { get_state_field(self.block_state(), FLUID_LEVEL_OFFSET, FLUID_LEVEL_MASK) as u8 }
+    pub fn set_fluid_level(&mut self, level: u8) {
+        self.set_block_state(set_state_field(self.block_state(), FLUID_LEVEL_OFFSET, FLUID_LEVEL_MASK, level as u16))
+    }
+
+    // nothing grows yet, crops and saplings will read these
+    #[allow(dead_code)]
+    pub fn growth_stage(self) -> u8 { get_state_field(self.block_state(), GROWTH_STAGE_OFFSET, GROWTH_STAGE_MASK) as u8 }
+    #[allow(dead_code)]
+    pub fn set_growth_stage(&mut self, stage: u8) {
+        self.set_block_state(set_state_field(self.block_state(), GROWTH_STAGE_OFFSET, GROWTH_STAGE_MASK, stage as u16))
+    }
+
+    /// The bits above the block state that aren't used by the layout yet
+    #[allow(dead_code)]
+    pub fn extra(self) -> u64 { (self.0 >> EXTRA_OFFSET) & EXTRA_MASK }
+    #[allow(dead_code)]
+    pub fn set_extra(&mut self, extra: u64) { self.0 = (self.0 & EXTRA_CLEAR_MASK) | ((extra & EXTRA_MASK) << EXTRA_OFFSET) }
+
+    pub fn should_create_face(self, other: Voxel) -> bool { self.is_filled() != other.is_filled() }
+}
+
+impl From<Voxel> for VoxelCoords {
+    fn from(v: Voxel) -> Self { v.coords() }
+}
+
+impl From<VoxelCoords> for Voxel {
+    fn from(c: VoxelCoords) -> Self { Voxel::new(voxel_helpers::get_index_from_coords(c)) }
+}
+
+impl From<Voxel> for BlockId {
+    fn from(v: Voxel) -> Self { v.block_type() }
+}
+
+impl From<Voxel> for usize {
+    fn from(v: Voxel) -> Self { v.index() }
+}
+
+impl From<usize> for Voxel {
+    fn from(index: usize) -> Self { Voxel::new(index) }
+}
+
+pub mod voxel_helpers {
+    use crate::{common::types::*};
+
+    pub fn get_coords_from_index(index: usize) -> VoxelCoords {
+        VoxelCoords {
+            x: (index % CHUNK_WIDTH) as u16,
+            y: (index / CHUNK_LAYER_SIZE) as u16,
+            z: ((index / CHUNK_WIDTH) % CHUNK_WIDTH) as u16,
         }
     }
 
@@ -39,20 +178,82 @@ pub mod voxel_helpers {
     }
 
     pub fn get_index(x: u16, y: u16, z: u16) -> usize {
-        usize::from((x & 0xf) | ((z & 0xF) << 4) | ((y & 0xFF) << 8))
+        debug_assert!(
+            (x as usize) < CHUNK_WIDTH && (y as usize) < CHUNK_HEIGHT && (z as usize) < CHUNK_WIDTH,
+            "voxel coordinates ({}, {}, {}) are outside of the chunk", x, y, z
+        );
+        (x as usize % CHUNK_WIDTH) + (z as usize % CHUNK_WIDTH) * CHUNK_WIDTH + (y as usize % CHUNK_HEIGHT) * CHUNK_LAYER_SIZE
     }
+}
 
-    pub fn set_coords(v: Voxel, coords: u64) -> Voxel { (v & COORDS_MASK) | coords << COORDS_OFFSET }
-    pub fn get_coords(v: Voxel) -> u64 { (v >> COORDS_OFFSET) & 0xFFFFFF }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::positions::WorldPos;
 
-    pub fn is_filled (v: Voxel) -> bool { ((v >> IS_FILLED_OFFSET) & 0b1) == 1 }
-    pub fn set_filled (v: Voxel, filled: bool) -> Voxel { (v & IS_FILLED_MASK) | ((filled as u64) << IS_FILLED_OFFSET) }
+    #[test]
+    fn block_state_fields_round_trip() {
+        let facings = [Facing::Up, Facing::Down, Facing::Left, Facing::Right, Facing::Forward, Facing::Backward];
+        for (index, facing) in facings.into_iter().enumerate() {
+            let mut voxel = Voxel::new(CHUNK_VOLUME - 1);
+            voxel.set_block_type(BlockId(7));
+            voxel.set_fluid_level(9);
+            voxel.set_growth_stage(index as u8);
+            voxel.set_facing(facing);
 
-	pub fn set_mesh_data (v: Voxel, mesh_data: u64) -> Voxel { (v & MESH_DATA_MASK) | (mesh_data << MESH_DATA_OFFSET)}
-	pub fn get_mesh_data (v: Voxel) -> u64 { (v >> MESH_DATA_OFFSET) & 0b111111 	}
+            assert_eq!(voxel.facing(), facing);
+            assert_eq!(voxel.growth_stage(), index as u8);
+            assert_eq!(voxel.fluid_level(), 9);
+            assert_eq!(voxel.block_type(), BlockId(7));
+            assert_eq!(voxel.index(), CHUNK_VOLUME - 1);
+        }
+    }
 
-	pub fn should_create_face (a: Voxel, b: Voxel) -> bool { is_filled(a) != is_filled(b) }
+    #[test]
+    fn structure_blocks_set_their_facing() {
+        let mut voxel = Voxel::new(5);
+        voxel.set_fluid_level(3);
+        let block = StructureBlock { position: WorldPos::new(0, 0, 0), block: Some(BlockId(2)), replace: true, facing: Facing::Left };
+        block.apply(&mut voxel);
+
+        assert_eq!(voxel.facing(), Facing::Left);
+        assert_eq!(voxel.fluid_level(), 0);
+        assert_eq!(voxel.index(), 5);
+    }
 
-    pub fn get_block_type(v: Voxel) -> u64 { (v >> BLOCK_TYPE_OFFSET) & BLOCK_TYPE_MASK } 
-    pub fn set_block_type(v: Voxel, block_type : BlockType) -> Voxel { (v & BLOCK_TYPE_CLEAR_MASK) | ((block_type as u64) << BLOCK_TYPE_OFFSET)}
+    #[test]
+    fn growth_stage_is_masked() {
+        let mut voxel = Voxel::default();
+        voxel.set_facing(Facing::Backward);
+        voxel.set_growth_stage(0xFF);
+        assert_eq!(voxel.growth_stage(), GROWTH_STAGE_MASK as u8);
+        assert_eq!(voxel.facing(), Facing::Backward);
+        assert_eq!(voxel.fluid_level(), 0);
+    }
+
+    #[test]
+    fn extra_bits_round_trip_without_touching_the_rest() {
+        let mut voxel = Voxel::new(CHUNK_VOLUME - 1);
+        voxel.set_filled(true);
+        voxel.set_block_type(BlockId(u16::MAX));
+        voxel.set_block_state(u16::MAX);
+        voxel.set_extra(u64::MAX);
+
+        assert_eq!(voxel.extra(), EXTRA_MASK);
+        assert_eq!(voxel.block_state(), u16::MAX);
+        assert_eq!(voxel.block_type(), BlockId(u16::MAX));
+        assert!(voxel.is_filled());
+        assert_eq!(voxel.index(), CHUNK_VOLUME - 1);
+
+        voxel.set_extra(0);
+        assert_eq!(voxel.extra(), 0);
+        assert_eq!(voxel.block_state(), u16::MAX);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "outside of the chunk")]
+    fn coordinates_outside_the_chunk_panic_in_debug_builds() {
+        voxel_helpers::get_index(0, CHUNK_HEIGHT as u16, 0);
+    }
 }
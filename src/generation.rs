@@ -1,3 +1,17 @@
 pub mod chunks;
 
-pub mod noise;
\ No newline at end of file
+pub mod noise;
+
+pub mod biomes;
+
+pub mod caves;
+
+pub mod ores;
+
+pub mod structures;
+
+pub mod rivers;
+
+pub mod seeds;
+
+pub mod resolved;
\ No newline at end of file
@@ -0,0 +1,170 @@
+use bevy_inspector_egui::Inspectable;
+use serde::Deserialize;
+
+use crate::common::blocks::{BlockId, BlockRegistry};
+use crate::common::content::{ContentError, load_ron, load_required};
+use crate::generation::noise::{NoiseLayer, sample_noise_vec2_01};
+use crate::systems::chunk_systems::NoiseConfiguration;
+
+pub const BIOME_REGISTRY_PATH: &str = "biomes.ron";
+
+#[derive(Clone, Default, Inspectable, Deserialize)]
+pub struct LayerBlock {
+	// the block is used when the block variant noise is at or below this
+	pub max_variant: f64,
+	pub block: String,
+}
+
+/// A band of blocks from the previous layer up to max_y, or all the way up when max_y is None
+#[derive(Clone, Default, Inspectable, Deserialize)]
+pub struct BlockLayer {
+	pub max_y: Option<i64>,
+	pub blocks: Vec<LayerBlock>,
+}
+
+#[derive(Clone, Default, Inspectable, Deserialize)]
+pub struct BiomeConfiguration {
+	pub name: String,
+
+	// base height the biome's terrain is built up from
+	#[inspectable(min = 0.0, max = 128.0)]
+	pub min_height: f64,
+
+	// added on top of min_height, interpolated across this range by (noise + height_offset) ^ height_exponent
+	pub height_range: (f64, f64),
+	pub height_offset: f64,
+	pub height_exponent: f64,
+
+	// the height noise for this biome, falls back to the global height noise when not set
+	pub noise_config: Option::<NoiseConfiguration>,
+
+	// the top surface_depth blocks of a column are picked from surface_blocks, everything under that from layers
+	#[inspectable(min = 0, max = 16)]
+	pub surface_depth: u8,
+	pub surface_blocks: Vec<LayerBlock>,
+	pub layers: Vec<BlockLayer>,
+	// used along the coast when this is the ocean biome, down to shore_depth below sea level
+	pub shore_block: Option<String>,
+}
+
+/// Everything loaded from the biome file
+#[derive(Deserialize)]
+pub struct BiomeFile {
+	pub biomes: Vec<BiomeConfiguration>,
+	// rows go from cold to hot and columns from dry to wet
+	pub climate_table: Vec<Vec<String>>,
+	pub ocean_biome: String,
+}
+
+pub fn load_biomes(path: &str, registry: &BlockRegistry) -> Result<BiomeFile, ContentError> {
+	let file: BiomeFile = load_ron(path)?;
+
+	for (i, biome) in file.biomes.iter().enumerate() {
+		if file.biomes[..i].iter().any(|other| other.name == biome.name) {
+			return Err(ContentError::DuplicateName(biome.name.clone()));
+		}
+	}
+
+	BiomeLookup::new(&file.biomes, &file.climate_table, &file.ocean_biome)?;
+
+	for biome in &file.biomes {
+		ResolvedBiome::new(biome, registry)?;
+	}
+
+	Ok(file)
+}
+
+pub fn load_default_biomes(registry: &BlockRegistry) -> BiomeFile {
+	load_required(BIOME_REGISTRY_PATH, |path| load_biomes(path, registry))
+}
+
+pub fn get_biome_index(biomes: &[BiomeConfiguration], name: &str) -> Option<usize> {
+	biomes.iter().position(|biome| biome.name == name)
+}
+
+pub fn get_biome_height(biome: &BiomeConfiguration, noise_layer: &NoiseLayer, x: f64, z: f64) -> f64 {
+	let noise = sample_noise_vec2_01(noise_layer, x, z);
+
+	let (min_range, max_range) = biome.height_range;
+	let shaped = f64::powf(noise + biome.height_offset, biome.height_exponent);
+	biome.min_height + min_range + shaped * (max_range - min_range)
+}
+
+/// The climate table and ocean biome with their names looked up
+#[derive(Clone)]
+pub struct BiomeLookup {
+	pub ocean: usize,
+	climate_table: Vec<Vec<usize>>,
+}
+
+impl BiomeLookup {
+	pub fn new(biomes: &[BiomeConfiguration], climate_table: &[Vec<String>], ocean_biome: &str) -> Result<Self, ContentError> {
+		let find = |name: &str| get_biome_index(biomes, name).ok_or_else(|| ContentError::UnknownBiome(name.to_string()));
+		Ok(Self {
+			ocean: find(ocean_biome)?,
+			climate_table: climate_table.iter()
+				.map(|row| row.iter().map(|name| find(name)).collect::<Result<Vec<usize>, ContentError>>())
+				.filter(|row| !matches!(row, Ok(row) if row.is_empty()))
+				.collect::<Result<_, ContentError>>()?,
+		})
+	}
+
+	/// Picks the table cell for a temperature and humidity, both expected to be in 0..1
+	pub fn get_land_biome(&self, temperature: f64, humidity: f64) -> usize {
+		let get_band = |value: f64, count: usize| ((value * count as f64) as usize).min(count - 1);
+
+		if self.climate_table.is_empty() { return self.ocean }
+
+		let row = &self.climate_table[get_band(temperature.max(0.0), self.climate_table.len())];
+		row[get_band(humidity.max(0.0), row.len())]
+	}
+}
+
+/// Blocks to pick between by the block variant noise, each up to its max variant
+pub type VariantBlocks = Vec<(f64, BlockId)>;
+
+/// A biome with its block names looked up in the block registry
+#[derive(Clone)]
+pub struct ResolvedBiome {
+	pub surface_depth: i64,
+	pub surface_blocks: VariantBlocks,
+	pub layers: Vec<(Option<i64>, VariantBlocks)>,
+	pub shore_block: Option<BlockId>,
+}
+
+pub(crate) fn resolve_blocks(blocks: &[LayerBlock], registry: &BlockRegistry) -> Result<VariantBlocks, ContentError> {
+	blocks.iter().map(|block| Ok((block.max_variant, registry.resolve(&block.block)?))).collect()
+}
+
+pub(crate) fn pick_block(blocks: &[(f64, BlockId)], block_variant: f64) -> Option<BlockId> {
+	blocks.iter()
+		.find(|(max_variant, _)| block_variant <= *max_variant)
+		.or(blocks.last())
+		.map(|(_, block)| *block)
+}
+
+impl ResolvedBiome {
+	pub fn new(biome: &BiomeConfiguration, registry: &BlockRegistry) -> Result<Self, ContentError> {
+		Ok(Self {
+			surface_depth: biome.surface_depth as i64,
+			surface_blocks: resolve_blocks(&biome.surface_blocks, registry)?,
+			layers: biome.layers.iter()
+				.map(|layer| Ok((layer.max_y, resolve_blocks(&layer.blocks, registry)?)))
+				.collect::<Result<_, ContentError>>()?,
+			shore_block: biome.shore_block.as_ref().map(|block| registry.resolve(block)).transpose()?,
+		})
+	}
+
+	/// depth is how many filled voxels sit between this one and the air above it
+	pub fn get_block(&self, world_y: i64, depth: i64, block_variant: f64) -> Option<BlockId> {
+		if depth < self.surface_depth && !self.surface_blocks.is_empty() {
+			return pick_block(&self.surface_blocks, block_variant);
+		}
+
+		let (_, blocks) = self.layers.iter()
+			.find(|(max_y, _)| max_y.is_none_or(|max_y| world_y < max_y))
+			.or(self.layers.last())?;
+
+		pick_block(blocks, block_variant)
+	}
+}
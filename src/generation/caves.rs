@@ -0,0 +1,153 @@
+use rand::Rng;
+
+use crate::ConfigurationState;
+use crate::common::types::*;
+use crate::common::blocks::BlockRegistry;
+use crate::common::voxels::voxel_helpers;
+use crate::generation::noise::*;
+
+struct WormSegment {
+	center: [f64;3],
+	radius: f64,
+	// entrances are allowed to carve all the way up through the surface
+	entrance: bool,
+}
+
+// a voxel that is open to the sky or sits under water shouldn't be carved into, it would leave holes in the sea floor
+fn is_open(voxel: Voxel, registry: &BlockRegistry) -> bool {
+	!voxel.is_filled() || !registry.is_solid(voxel.block_type())
+}
+
+/// Carves caves out of a generated chunk. Noise caves stay under the surface, worm tunnels are
+/// regenerated from every nearby chunk's seed so they line up no matter which chunk is built first.
+/// surface_heights are the height map's column heights, indexed by x + z * CHUNK_WIDTH
+pub fn carve_caves(coords: Vector3, voxels: &mut VoxelCollection, surface_heights: &[i64], config: &ConfigurationState, sources: &NoiseSources, registry: &BlockRegistry) {
+	let caves = &config.cave_configuration;
+	if !caves.enabled { return }
+
+	let air = Voxel::default();
+
+	let offset_x = coords.x as i64 * CHUNK_DIMENSIONS.x;
+	let offset_y = coords.y as i64 * CHUNK_DIMENSIONS.y;
+	let offset_z = coords.z as i64 * CHUNK_DIMENSIONS.z;
+
+	let segments = get_worm_segments(coords, config);
+
+	for x in 0..CHUNK_WIDTH as u16 {
+		let x0 = (x as i64 + offset_x) as f64;
+		for z in 0..CHUNK_WIDTH as u16 {
+			let z0 = (z as i64 + offset_z) as f64;
+
+			// only the segments that can reach this column
+			let column_segments: Vec<&WormSegment> = segments.iter()
+				.filter(|segment| f64::abs(segment.center[0] - x0) <= segment.radius && f64::abs(segment.center[2] - z0) <= segment.radius)
+				.collect();
+
+			let surface_height = surface_heights[x as usize + z as usize * CHUNK_WIDTH];
+
+			// how far under the nearest open voxel above in this chunk we are, overhangs and cave ceilings
+			// are only known from the scan. the surface height covers the voxels above the chunk's top
+			let mut open_depth = i64::MAX / 2;
+			let mut above_is_fluid = false;
+			for y in (0..CHUNK_HEIGHT as u16).rev() {
+				let index = voxel_helpers::get_index(x, y, z);
+				let voxel = voxels[index];
+
+				if is_open(voxel, registry) {
+					open_depth = 0;
+					above_is_fluid = voxel.is_filled();
+					continue;
+				}
+
+				open_depth += 1;
+				if above_is_fluid && open_depth == 1 { continue }
+
+				let world_y = y as i64 + offset_y;
+				let y0 = world_y as f64;
+				let depth = open_depth.min(surface_height.saturating_sub(world_y) + 1);
+
+				let in_worm = column_segments.iter().any(|segment| {
+					let (dx, dy, dz) = (segment.center[0] - x0, segment.center[1] - y0, segment.center[2] - z0);
+					dx * dx + dy * dy + dz * dz <= segment.radius * segment.radius
+						&& (segment.entrance || depth > caves.surface_margin as i64)
+				});
+
+				if in_worm || (depth > caves.surface_margin as i64 && world_y >= caves.min_y && is_noise_cave(sources, config, x0, y0, z0)) {
+					let mut carved = air;
+					carved.set_index(index);
+					voxels[index] = carved;
+				}
+			}
+		}
+	}
+}
+
+// cheese caves are the big open blobs where the noise is high, spaghetti caves are the thin tubes where two noise fields both cross the middle
+fn is_noise_cave(sources: &NoiseSources, config: &ConfigurationState, x: f64, y: f64, z: f64) -> bool {
+	let caves = &config.cave_configuration;
+
+	if sample_noise_01(&sources.layers.cheese_caves, [x, y, z]) > caves.cheese_threshold {
+		return true;
+	}
+
+	f64::abs(sample_noise_01(&sources.layers.spaghetti_caves, [x, y, z]) - 0.5) < caves.spaghetti_width
+		&& f64::abs(sample_noise_01(&sources.layers.spaghetti_second_caves, [x, y, z]) - 0.5) < caves.spaghetti_width
+}
+
+// walks every worm started by a chunk within worm_range and keeps the spheres that overlap this chunk
+fn get_worm_segments(coords: Vector3, config: &ConfigurationState) -> Vec<WormSegment> {
+	let caves = &config.cave_configuration;
+	let mut segments = vec![];
+
+	let chunk_x = coords.x as i64;
+	let chunk_z = coords.z as i64;
+	let min = [
+		(chunk_x * CHUNK_DIMENSIONS.x) as f64,
+		(coords.y as i64 * CHUNK_DIMENSIONS.y) as f64,
+		(chunk_z * CHUNK_DIMENSIONS.z) as f64,
+	];
+	let max = [min[0] + CHUNK_DIMENSIONS.x as f64, min[1] + CHUNK_DIMENSIONS.y as f64, min[2] + CHUNK_DIMENSIONS.z as f64];
+
+	let range = caves.worm_range as i64;
+	for source_x in chunk_x - range..=chunk_x + range {
+		for source_z in chunk_z - range..=chunk_z + range {
+			// worms are seeded per chunk column so every chunk stacked on it agrees on them
+			let mut rng = get_chunk_rng(caves.worm_seed, source_x, 0, source_z);
+			let worm_count = rng.gen_range(0..=caves.worms_per_chunk);
+
+			for _ in 0..worm_count {
+				let entrance = rng.gen_bool(caves.entrance_chance.clamp(0.0, 1.0));
+				let mut position = [
+					(source_x * CHUNK_DIMENSIONS.x) as f64 + rng.gen_range(0.0..CHUNK_DIMENSIONS.x as f64),
+					if entrance { config.sea_level } else { rng.gen_range(caves.min_y as f64..config.sea_level.max(caves.min_y as f64 + 1.0)) },
+					(source_z * CHUNK_DIMENSIONS.z) as f64 + rng.gen_range(0.0..CHUNK_DIMENSIONS.z as f64),
+				];
+				let mut yaw = rng.gen_range(0.0..std::f64::consts::TAU);
+				// entrances keep heading up so they come out somewhere on the surface
+				let base_pitch: f64 = if entrance { rng.gen_range(0.3..0.7) } else { 0.0 };
+				let mut pitch: f64 = base_pitch + rng.gen_range(-0.3..0.3);
+				let base_radius = rng.gen_range(caves.worm_radius.0..=caves.worm_radius.1.max(caves.worm_radius.0));
+
+				for step in 0..caves.worm_length {
+					yaw += rng.gen_range(-0.3..0.3);
+					pitch = (pitch * 0.8 + base_pitch * 0.2 + rng.gen_range(-0.2..0.2)).clamp(-1.0, 1.0);
+
+					position[0] += f64::cos(yaw) * f64::cos(pitch) * caves.worm_step;
+					position[1] += f64::sin(pitch) * caves.worm_step;
+					position[2] += f64::sin(yaw) * f64::cos(pitch) * caves.worm_step;
+
+					// thinner at the ends so tunnels don't stop in a flat wall
+					let progress = step as f64 / caves.worm_length.max(1) as f64;
+					let radius = base_radius * (0.5 + 0.5 * f64::sin(progress * std::f64::consts::PI));
+
+					let overlaps = (0..3).all(|axis| position[axis] + radius >= min[axis] && position[axis] - radius <= max[axis]);
+					if overlaps {
+						segments.push(WormSegment { center: position, radius, entrance });
+					}
+				}
+			}
+		}
+	}
+
+	segments
+}
@@ -1,9 +1,24 @@
 use crate::ConfigurationState;
 use crate::common::types::*;
+use crate::common::blocks::BlockRegistry;
 use crate::common::voxels::voxel_helpers;
+use crate::common::storage::VoxelStorage;
+use crate::generation::biomes::*;
 use crate::generation::noise::*;
-use noise::*;
-
+use crate::generation::resolved::ResolvedConfiguration;
+use crate::generation::rivers::get_river_column;
+use crate::generation::{caves, ores};
+
+
+/// Builds a chunk's voxels from the terrain, caves and ores. Structures are added later once the
+/// neighbours exist. Only reads its arguments, so it can run on a task off the main thread
+pub fn generate_chunk(coords: Vector3Int, config: &ConfigurationState, resolved: &ResolvedConfiguration, sources: &NoiseSources, registry: &BlockRegistry) -> VoxelStorage {
+	let coords = Vector3 { x: coords.x as f64, y: coords.y as f64, z: coords.z as f64 };
+	let (mut voxels, surface_heights) = get_height_map(coords, config, resolved, sources);
+	caves::carve_caves(coords, &mut voxels, &surface_heights, config, sources, registry);
+	ores::place_ores(coords, &mut voxels, &resolved.ores);
+	VoxelStorage::from_voxels(&voxels)
+}
 
 #[allow(dead_code)]
 fn interpolate (range : (f64,f64), value: f64) -> f64 {
@@ -12,188 +27,191 @@ fn interpolate (range : (f64,f64), value: f64) -> f64 {
 	lower + value * range
 }
 
-pub fn get_height_map(coords: Vector3, config: ConfigurationState) -> VoxelCollection {
-	// TODO: pool and create a resource for the noise gen
-	let mut voxels: VoxelCollection = vec![0;16*16*128];
-    let height_map_gen = OpenSimplex::new();
+// the biome noise decides between ocean and land, then the climate picks the land biome
+fn get_biome_at(sources: &NoiseSources, config: &ConfigurationState, lookup: &BiomeLookup, x: f64, z: f64) -> (f64, usize) {
+	let biome_noise = f64::powf(sample_noise_vec2_01(&sources.layers.biome, x, z), 1.2);
+	if biome_noise <= config.ocean_threshold {
+		return (biome_noise, lookup.ocean);
+	}
+
+	let temperature = sample_noise_vec2_01(&sources.layers.temperature, x, z);
+	let humidity = sample_noise_vec2_01(&sources.layers.humidity, x, z);
+	(biome_noise, lookup.get_land_biome(temperature, humidity))
+}
+
+// the height map decides the rough shape, then 3d noise pushes the surface up or down to carve out overhangs and arches
+fn is_solid(sources: &NoiseSources, config: &ConfigurationState, x: f64, y: f64, z: f64, surface_height: f64, density_scale: f64) -> bool {
+	let terrain = &config.terrain_density_configuration;
+	let gradient = surface_height - y;
+	let amplitude = terrain.amplitude * density_scale;
+
+	let density = if !terrain.enabled || gradient.abs() > amplitude {
+		gradient
+	} else {
+		let noise = sample_noise_01(&sources.layers.terrain_density, [x, y * terrain.vertical_scale, z]);
+		gradient + (noise * 2.0 - 1.0) * amplitude
+	};
+
+	if density >= 0.0 { return true }
+
+	let islands = &config.floating_island_configuration;
+	let island_offset = f64::abs(y - islands.height);
+	if !islands.enabled || island_offset >= islands.thickness { return false }
+
+	// islands thin out toward the top and bottom of their band
+	let noise = sample_noise_01(&sources.layers.floating_islands, [x, y, z]);
+	noise - islands.threshold - (island_offset / islands.thickness) * (1.0 - islands.threshold) > 0.0
+}
+
+/// Builds the chunk's terrain, along with the surface height of each column indexed by x + z * CHUNK_WIDTH.
+/// Columns outside the biome range have no terrain and get i64::MIN
+pub fn get_height_map(coords: Vector3, config: &ConfigurationState, resolved: &ResolvedConfiguration, sources: &NoiseSources) -> (VoxelCollection, Vec<i64>) {
+	let mut voxels: VoxelCollection = vec![Voxel::default(); CHUNK_VOLUME];
+	let mut surface_heights = vec![i64::MIN; CHUNK_LAYER_SIZE];
+
+	let water = resolved.water;
+	let river_bed_blocks = &resolved.river_bed_blocks;
+	let biomes = &resolved.biomes;
+	let lookup = &resolved.biome_lookup;
+	if biomes.is_empty() { return (voxels, surface_heights) }
 
 	let mut y0: f64;
-	let mut _blob_noise: f64;
 	let mut block_variant_noise: f64;
-	let mut height_noise: f64;
-	let mut height: f64 = 0.0;
-	let mut height_noise_smoother;
-	let mut depth_adjust_noise;
+	let mut height: f64;
 	let mut depth_adjust;
-	let mut biome_noise;
-	let mut ocean_height;
-	let mut plains_height;
-	let mut mountain_height;
-
-	let height_seed = config.height_noise_configuration.seed;
-	let depth_adjust_seed= config.depth_adjust_noise_configuration.seed;
-	let biome_seed = config.biome_noise_configuration.seed;
-
-	let height_noise_freq = config.height_noise_configuration.freq;
-	let height_noise_smooth_freq = config.height_noise_smooth_configuration.freq;
-	let depth_adjust_noise_freq = config.depth_adjust_noise_configuration.freq;
-	let biome_noise_freq = config.biome_noise_configuration.freq;
-	
-	let height_noise_octaves = config.height_noise_configuration.octaves;
-	let height_noise_smooth_octaves = config.height_noise_smooth_configuration.octaves;
-	let biome_noise_octaves = config.biome_noise_configuration.octaves;
-	let depth_adjust_noise_octaves = config.depth_adjust_noise_configuration.octaves;
-
-	let offset_x = coords.x * 16.0;
-	let offset_z = coords.z * 16.0; 
-
-	let mut height_noise_points = [0.0, 0.0];
-	let mut height_noise_smoother_points = [0.0, 0.0];
-	let mut depth_adjust_points = [0.0, 0.0];
-	let mut biome_noise_points = [0.0, 0.0];
-
-	for x in 0..16u16 {
+
+	let offset_x = coords.x * CHUNK_DIMENSIONS.x as f64;
+	let offset_y = coords.y as i64 * CHUNK_DIMENSIONS.y;
+	let offset_z = coords.z * CHUNK_DIMENSIONS.z as f64; 
+
+	let shore_min_y = (config.sea_level - config.shore_depth as f64) as i64;
+
+	// biomes are sampled on a coarse grid around the chunk, and each column's height is blended from every
+	// sample within the blend radius so it stays continuous no matter which biomes meet
+	let grid_size = config.biome_grid_size.max(1) as i64;
+	let blend_radius = config.biome_blend_radius.max(1.0);
+	let blend_radius_squared = blend_radius * blend_radius;
+	let grid_margin = blend_radius.ceil() as i64;
+	let (grid_min_x, grid_max_x) = ((offset_x as i64 - grid_margin).div_euclid(grid_size), (offset_x as i64 + CHUNK_DIMENSIONS.x + grid_margin).div_euclid(grid_size));
+	let (grid_min_z, grid_max_z) = ((offset_z as i64 - grid_margin).div_euclid(grid_size), (offset_z as i64 + CHUNK_DIMENSIONS.z + grid_margin).div_euclid(grid_size));
+
+	let mut biome_grid = vec![];
+	for grid_x in grid_min_x..=grid_max_x {
+		for grid_z in grid_min_z..=grid_max_z {
+			let sample_x = (grid_x * grid_size) as f64;
+			let sample_z = (grid_z * grid_size) as f64;
+			let (_, sample_biome) = get_biome_at(sources, config, lookup, sample_x, sample_z);
+			biome_grid.push((sample_x, sample_z, sample_biome));
+		}
+	}
+
+	let mut biome_weights = vec![0.0; biomes.len()];
+	let max_surface_depth = config.biomes.iter().map(|biome| biome.surface_depth as i64).max().unwrap_or(0);
+
+	for x in 0..CHUNK_WIDTH as u16 {
 		let x0 = x as f64 + offset_x;
-		height_noise_points[0] = x0 * height_noise_freq;
-		height_noise_smoother_points[0] = x0 * height_noise_smooth_freq;
-		depth_adjust_points[0] = x0 * depth_adjust_noise_freq;
-		biome_noise_points[0] = x0 * biome_noise_freq;
-		for z in 0..16u16 {
+		for z in 0..CHUNK_WIDTH as u16 {
 			let z0 = z as f64 + offset_z;
-			height_noise_points[1] = z0 * height_noise_freq;
-			height_noise_smoother_points[1] = z0 * height_noise_smooth_freq;
-			depth_adjust_points[1] = z0 * depth_adjust_noise_freq;
-			biome_noise_points[1] = z0 * biome_noise_freq;
-
-			height_noise = noise_with_octaves_vec2_01(height_map_gen,height_noise_points, height_noise_octaves, height_seed, 1.0);
-			height_noise = f64::powf(height_noise, 1.2);
-
-			height_noise_smoother = noise_with_octaves_vec2_01(height_map_gen, height_noise_smoother_points, height_noise_smooth_octaves, height_seed, 1.0);
-			depth_adjust_noise = noise_with_octaves_vec2_01(height_map_gen, depth_adjust_points, depth_adjust_noise_octaves , depth_adjust_seed, 1.0);
-			depth_adjust = depth_adjust_noise as i16 * 10 - 5;
-
-			biome_noise = f64::powf(noise_with_octaves_vec2_01(height_map_gen, biome_noise_points, biome_noise_octaves, biome_seed, 1.0), 1.2);
-			// TODO: Weight biomes on interpolation by distance from edge, so that things like Mountains and oceans
-			// aren't effecting each others results very much
-			let (_, ocean_max_noise) = config.ocean_biome_config.range;
-			let (plains_min_noise, plains_max_noise) = config.plains_biome_config.range;
-			let (mountain_min_noise, _) = config.mountains_biome_config.range;
-
-			let (ocean_min_height, ocean_max_height) = config.ocean_biome_config.height_range;
-			let (plains_min_height, _) = config.plains_biome_config.height_range;
-			let (mountain_min_height, _) = config.mountains_biome_config.height_range;
-
-			
-			ocean_height = interpolate(config.ocean_biome_config.height_range, f64::powf(height_noise_smoother, 2.0)) + ocean_min_height;
-			plains_height = interpolate(config.plains_biome_config.height_range, f64::powf(height_noise_smoother+ 0.2, 2.0)) + plains_min_height;
-			mountain_height = interpolate(config.mountains_biome_config.height_range, f64::powf(height_noise, 1.2)) + mountain_min_height;
-
-			let biome_smoothing = config.biome_smoothing;
-
-			if biome_noise <= ocean_max_noise {
-				height = ocean_height;
-			} else if biome_noise>= plains_min_noise && biome_noise <= plains_max_noise {
-				height = plains_height;
-			} else if biome_noise >= mountain_min_noise {
-				height = mountain_height;
-			}
 
-			if biome_noise > ocean_max_noise - biome_smoothing && biome_noise <= ocean_max_noise {
-				height = interpolate((plains_height, ocean_height), f64::abs(ocean_max_noise - biome_noise) / biome_smoothing);
+			depth_adjust = sample_noise_vec2_01(&sources.layers.depth_adjust, x0, z0) as i16 * 10 - 5;
+
+			let (biome_noise, biome_index) = get_biome_at(sources, config, lookup, x0, z0);
+
+			if biome_noise <= config.biome_range.0 || biome_noise >= config.biome_range.1 {
+				continue;
 			}
 
-			if biome_noise > plains_max_noise - biome_smoothing && biome_noise <= plains_max_noise {
-				height = interpolate((mountain_height, plains_height), f64::abs(plains_max_noise - biome_noise) / biome_smoothing);
+			// smooth falloff kernel, samples at the edge of the radius don't contribute anything
+			biome_weights.iter_mut().for_each(|weight| *weight = 0.0);
+			let mut total_weight = 0.0;
+			for &(sample_x, sample_z, sample_biome) in &biome_grid {
+				let distance_squared = (sample_x - x0) * (sample_x - x0) + (sample_z - z0) * (sample_z - z0);
+				if distance_squared >= blend_radius_squared { continue }
+
+				let falloff = 1.0 - distance_squared / blend_radius_squared;
+				biome_weights[sample_biome] += falloff * falloff;
+				total_weight += falloff * falloff;
 			}
 
-			let mut int_height = height as u8;
+			height = if total_weight > 0.0 {
+				biome_weights.iter().enumerate()
+					.filter(|(_, &weight)| weight > 0.0)
+					.map(|(index, &weight)| weight * get_biome_height(&config.biomes[index], sources.layers.get_biome_height(index), x0, z0))
+					.sum::<f64>() / total_weight
+			} else {
+				get_biome_height(&config.biomes[biome_index], sources.layers.get_biome_height(biome_index), x0, z0)
+			};
+
+			let biome = &biomes[biome_index];
+			let shore_block = if f64::abs(biome_noise - config.ocean_threshold) <= config.shore_width {
+				biomes[lookup.ocean].shore_block
+			} else {
+				None
+			};
+
+			let mut int_height = height as i64;
 
 			// max height based on biome?
-			//int_height = interpolated as u8;
+			//int_height = interpolated as i64;
 
 			if i16::abs(depth_adjust) <= 2 { 
-				let mod_height = int_height as i16 + depth_adjust;
-				int_height = (mod_height & 0xFF) as u8; 
+				int_height += depth_adjust as i64;
 			}
 
-			for y in 0..128u16 {
-				y0 = y as f64;
-
+			let mut water_level = if config.generate_ocean_water { config.sea_level as i64 - 1 } else { i64::MIN };
+			let river = get_river_column(sources, config, x0, z0, int_height);
+			if let Some(river) = &river {
+				int_height = river.surface_height;
+				water_level = water_level.max(river.water_level);
+			}
+			let density_scale = river.as_ref().map_or(1.0, |river| river.density_scale);
+			surface_heights[x as usize + z as usize * CHUNK_WIDTH] = int_height;
+			let in_river_channel = river.as_ref().is_some_and(|river| river.in_channel);
+
+			// walk down from a little above the chunk so surface blocks still line up when the surface
+			// sits right on a chunk border. depth counts the filled voxels since the last air voxel
+			let mut depth = 0i64;
+			for world_y in (offset_y..offset_y + CHUNK_DIMENSIONS.y + max_surface_depth).rev() {
+				y0 = world_y as f64;
+				let filled = is_solid(sources, config, x0, y0, z0, int_height as f64, density_scale);
+				depth = if filled { depth + 1 } else { 0 };
+
+				// everything above here is only for tracking depth
+				if world_y >= offset_y + CHUNK_DIMENSIONS.y { continue }
+
+				let y = (world_y - offset_y) as u16;
 				let index = voxel_helpers::get_index(x, y, z);
 
-				let mut voxel = index as u64;
+				let mut voxel = Voxel::new(index);
 
-				if biome_noise <= config.biome_range.0 || biome_noise >= config.biome_range.1 {
-					voxel = voxel_helpers::set_filled(voxel, false);
-					voxels[index] = voxel;
-					continue;
-				}
-
-				if y <= int_height as u16 { 
-					voxel = voxel_helpers::set_filled(voxel, true);
-				} else if y < (ocean_max_height as u16) {
-					if config.generate_ocean_water {
-						voxel = voxel_helpers::set_filled(voxel, true);
-						voxel = voxel_helpers::set_block_type(voxel , BlockType::Water);
-					}
+				if filled { 
+					voxel.set_filled(true);
+				} else if world_y > int_height && world_y <= water_level {
+					voxel.set_filled(true);
+					voxel.set_block_type(water);
+					voxel.set_fluid_level(0);
 					voxels[index] = voxel;
 					continue;
 				} else {
 					voxels[index] = voxel;
 					continue;
 				}
-				
-				//if y >= 90 && y <= 110 {
-				//	blob_noise = noise_with_octaves_01(height_map_gen,[x0 * 0.0125, z0 * 0.0125, y0 * 0.0125], 8, 1049, 1.0);
-				//	blob_noise = f64::powf(blob_noise, 1.2) * (1.0 - f64::abs((100 - y as i16) as f64 / 20.0));
-				//	if blob_noise >= 0.8 { voxel = voxel_helpers::set_filled(voxel); }
-				//}
-
-				block_variant_noise = noise_with_octaves_01(height_map_gen,[x0  * 0.025, y0 * 0.025, z0 * 0.025], 3, 12984, 0.7);
+
+				block_variant_noise = sample_noise_01(&sources.layers.block_variant, [x0, y0, z0]);
 				block_variant_noise = f64::powf(block_variant_noise, 2.0);
-				let block_type: BlockType = if y < 10 {
-					BlockType::DarkStone
-				} 
-				else if f64::abs(biome_noise - ocean_max_noise) <= 0.01 {
-					BlockType::Sand
-				}
-				else if y < 50  {
-					if biome_noise < ocean_max_noise {
-						if block_variant_noise <= 0.1 {
-							BlockType::Stone
-						} else {
-							BlockType::Sand
-						}
-					} else if biome_noise < plains_max_noise {
-						if block_variant_noise <= 0.1 {
-							BlockType::Sand
-						} else {
-							BlockType::Grass
-						}
-					} else {
-						if block_variant_noise <= 0.1 {
-							BlockType::Sand
-						} else {
-							BlockType::Grass
-						}
-					}
-				} 
-				else if y < 70 {
-					if block_variant_noise <= 0.3 {
-						BlockType::Dirt	
-					} else if block_variant_noise <= 0.7 {
-						BlockType::Stone
-					} else {
-						BlockType::DarkStone
-					}
-				} else if y < 90 {
-					BlockType::Stone
-				} else if y < 100 {
-					BlockType::Snow
+
+				let block_type = if in_river_channel && depth <= config.river_configuration.bed_depth as i64 {
+					pick_block(river_bed_blocks, block_variant_noise)
 				} else {
-					BlockType::Ice
+					match shore_block {
+						Some(shore_block) if world_y >= shore_min_y => Some(shore_block),
+						_ => biome.get_block(world_y, depth - 1, block_variant_noise),
+					}
 				};
-				voxel = voxel_helpers::set_block_type(voxel, block_type);
+
+				if let Some(block_type) = block_type {
+					voxel.set_block_type(block_type);
+				}
 				voxels[index] = voxel;
 
 			}
@@ -201,5 +219,5 @@ pub fn get_height_map(coords: Vector3, config: ConfigurationState) -> VoxelColle
 
 	}
 
-	voxels
+	(voxels, surface_heights)
 }
@@ -1,102 +1,239 @@
+use bevy::{prelude::{FromWorld, World}, utils::HashMap};
+use bevy_inspector_egui::Inspectable;
 use noise::*;
+use rand::{SeedableRng, rngs::StdRng};
+use serde::Deserialize;
+
+use crate::ConfigurationState;
+use crate::systems::chunk_systems::NoiseConfiguration;
+
+/// A configured noise layer, ready to sample
+pub type NoiseLayer = FractalNoise<OpenSimplex>;
+
+/// One seeded noise generator per seed. Building a generator shuffles a permutation table, so
+/// they're built once up front instead of on every chunk. The layers wrapping them are built
+/// at the same time, so sampling doesn't set up a fractal for every voxel
+#[derive(Clone)]
+pub struct NoiseSources {
+	sources: HashMap<i32, OpenSimplex>,
+	pub layers: NoiseLayers,
+}
+
+/// Every noise layer the generator samples, built from the configuration
+#[derive(Clone)]
+pub struct NoiseLayers {
+	pub height: NoiseLayer,
+	pub depth_adjust: NoiseLayer,
+	pub block_variant: NoiseLayer,
+	pub biome: NoiseLayer,
+	pub temperature: NoiseLayer,
+	pub humidity: NoiseLayer,
+	pub terrain_density: NoiseLayer,
+	pub floating_islands: NoiseLayer,
+	pub cheese_caves: NoiseLayer,
+	pub spaghetti_caves: NoiseLayer,
+	pub spaghetti_second_caves: NoiseLayer,
+	pub rivers: NoiseLayer,
+	// indexed the same as the configuration's biomes, None for biomes that use the default height noise
+	pub biome_heights: Vec<Option<NoiseLayer>>,
+}
 
-fn normalize(value: f64) -> f64 { value + 0.5 }
-
-pub fn noise_with_octaves(gen: impl NoiseFn<[f64;3]>, point: [f64;3], octaves: u8, seed: i32, persist: f64) -> f64 {
-	let mut noise = 0.0;
-	let mut freq_sum = 0.0;
-	let mut freq;
-	let mut weight;
-	let mut persistence = 1.0;
-	let mut points = [0.0, 0.0, 0.0];
-	for i in 0..octaves
-	{
-		freq = (1 << i) as f64;
-		weight = 1.0 / freq;
-
-		points[0] = freq * point[0] + seed as f64;
-		points[1] = freq * point[1] + seed as f64;
-		points[2] = freq * point[2] + seed as f64;
-
-		let temp_noise = gen.get(points);
-		noise += persistence * weight * temp_noise;
-		persistence *= persist;
-		freq_sum += weight;
+impl NoiseLayers {
+	/// The height noise for a biome, falling back to the default height noise
+	pub fn get_biome_height(&self, biome_index: usize) -> &NoiseLayer {
+		self.biome_heights.get(biome_index).and_then(Option::as_ref).unwrap_or(&self.height)
 	}
+}
 
-	return noise / freq_sum;	
+fn create_source(seed: i32) -> OpenSimplex {
+	OpenSimplex::new().set_seed(seed as u32)
 }
 
-pub fn noise_with_octaves_vec2(gen: impl NoiseFn<[f64;2]>, point: [f64;2], octaves: u8, seed: i32, persist: f64) -> f64 {
-	let mut noise = 0.0;
-	let mut freq_sum = 0.0;
-	let mut freq;
-	let mut weight;
-	let mut persistence = 1.0;
-
-	let mut points : [f64;2] = [0.0,0.0];
-	for i in 0..octaves
-	{
-		freq = (1 << i) as f64;
-		weight = 1.0 / freq;
-
-		points[0] = freq * point[0] + seed as f64;
-		points[1] = freq * point[1] + seed as f64;
-		let temp_noise = gen.get(points);
-		noise += persistence * weight * temp_noise;
-		persistence *= persist;
-		freq_sum += weight;
+impl NoiseSources {
+	pub fn from_config(config: &ConfigurationState) -> Self {
+		let mut sources = HashMap::<i32, OpenSimplex>::default();
+		let caves = &config.cave_configuration;
+
+		let noise_configs = [
+			&config.height_noise_configuration,
+			&config.depth_adjust_noise_configuration,
+			&config.biome_noise_configuration,
+			&config.temperature_noise_configuration,
+			&config.humidity_noise_configuration,
+			&config.terrain_density_configuration.noise_config,
+			&config.floating_island_configuration.noise_config,
+			&caves.cheese_noise_config,
+			&caves.spaghetti_noise_config,
+			&caves.spaghetti_second_noise_config,
+			&config.river_configuration.noise_config,
+			&config.block_variant_noise_configuration,
+		];
+		let biome_noise_configs = config.biomes.iter().filter_map(|biome| biome.noise_config.as_ref());
+
+		for noise_config in noise_configs.into_iter().chain(biome_noise_configs) {
+			sources.entry(noise_config.seed).or_insert_with(|| create_source(noise_config.seed));
+		}
+
+		let layer = |noise_config: &NoiseConfiguration| FractalNoise::from_config(sources[&noise_config.seed], noise_config);
+		let layers = NoiseLayers {
+			height: layer(&config.height_noise_configuration),
+			depth_adjust: layer(&config.depth_adjust_noise_configuration),
+			block_variant: layer(&config.block_variant_noise_configuration),
+			biome: layer(&config.biome_noise_configuration),
+			temperature: layer(&config.temperature_noise_configuration),
+			humidity: layer(&config.humidity_noise_configuration),
+			terrain_density: layer(&config.terrain_density_configuration.noise_config),
+			floating_islands: layer(&config.floating_island_configuration.noise_config),
+			cheese_caves: layer(&caves.cheese_noise_config),
+			spaghetti_caves: layer(&caves.spaghetti_noise_config),
+			spaghetti_second_caves: layer(&caves.spaghetti_second_noise_config),
+			rivers: layer(&config.river_configuration.noise_config),
+			biome_heights: config.biomes.iter().map(|biome| biome.noise_config.as_ref().map(layer)).collect(),
+		};
+
+		Self { sources, layers }
 	}
 
-	return noise / freq_sum;	
+	/// The generator for a seed. Every seed in the configuration is built up front, so asking for
+	/// any other one is a bug
+	pub fn get(&self, seed: i32) -> &OpenSimplex {
+		match self.sources.get(&seed) {
+			Some(source) => source,
+			None => panic!("no noise source built for seed {}", seed),
+		}
+	}
 }
 
-pub fn noise_with_octaves_01(gen: impl NoiseFn<[f64;3]>, point: [f64;3], octaves: u8, seed: i32, persist: f64) -> f64 {
-	let mut noise = 0.0;
-	let mut freq_sum = 0.0;
-	let mut freq;
-	let mut weight;
-	let mut persistence = 1.0;
-	let mut points = [0.0, 0.0, 0.0];
-	for i in 0..octaves
-	{
-		freq = (1 << i) as f64;
-		weight = 1.0 / freq;
-
-		points[0] = freq * point[0] + seed as f64;
-		points[1] = freq * point[1] + seed as f64;
-		points[2] = freq * point[2] + seed as f64;
-
-		let temp_noise = gen.get(points);
-		noise += persistence * weight * normalize(temp_noise);
-		persistence *= persist;
-		freq_sum += weight;
+impl FromWorld for NoiseSources {
+	fn from_world(world: &mut World) -> Self {
+		NoiseSources::from_config(world.resource::<ConfigurationState>())
 	}
+}
+
+/// A random generator that always gives the same numbers for the same seed and chunk
+pub fn get_chunk_rng(seed: i32, x: i64, y: i64, z: i64) -> StdRng {
+	// mix the coordinates with large odd constants so neighbouring chunks don't get similar seeds
+	let mixed = (seed as u64)
+		^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+		^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+		^ (z as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+	StdRng::seed_from_u64(mixed)
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Debug, Inspectable, Deserialize)]
+pub enum FractalMode {
+	// plain fractal brownian motion
+	#[default]
+	Fbm,
+	// sharp ridges where the noise crosses zero, each octave is weighted by the one before it so the ridges stay crisp
+	Ridged,
+	// rounded lumps, the absolute value of each octave
+	Billow,
+}
 
-	return noise / freq_sum;	
+// samples for the domain warp are taken this far away on each axis so they don't line up with the main sample
+const WARP_OFFSETS: [f64; 4] = [17.31, 41.93, 73.17, 97.71];
+
+/// Layers octaves of a noise function over any number of dimensions. Every mode keeps the source's
+/// -0.5..0.5 range when normalized, so the 0..1 helpers work the same for all of them
+#[derive(Copy, Clone)]
+pub struct FractalNoise<T> {
+	pub source: T,
+	pub octaves: u8,
+	pub frequency: f64,
+	// how much the frequency grows each octave
+	pub lacunarity: f64,
+	// how much the amplitude shrinks each octave
+	pub gain: f64,
+	pub mode: FractalMode,
+	// divide by the total amplitude so adding octaves doesn't change the range
+	pub normalize: bool,
+	// how far the sample point is pushed around by the source itself, in noise space. 0 turns warping off
+	pub warp: f64,
 }
 
+impl<T> FractalNoise<T> {
+	pub fn new(source: T) -> Self {
+		Self {
+			source,
+			octaves: 1,
+			frequency: 1.0,
+			lacunarity: 2.0,
+			gain: 0.5,
+			mode: FractalMode::Fbm,
+			normalize: true,
+			warp: 0.0,
+		}
+	}
 
-pub fn noise_with_octaves_vec2_01(gen: impl NoiseFn<[f64;2]>, point: [f64;2], octaves: u8, seed: i32, persist: f64) -> f64 {
-	let mut noise = 0.0;
-	let mut freq_sum = 0.0;
-	let mut freq;
-	let mut weight;
-	let mut persistence = 1.0;
-
-	let mut points : [f64;2] = [0.0,0.0];
-	for i in 0..octaves
-	{
-		freq = (1 << i) as f64;
-		weight = 1.0 / freq;
-
-		points[0] = freq * point[0] + seed as f64;
-		points[1] = freq * point[1] + seed as f64;
-		let temp_noise = gen.get(points);
-		noise += persistence * weight * normalize(temp_noise);
-		persistence *= persist;
-		freq_sum += weight;
+	pub fn from_config(source: T, noise_config: &NoiseConfiguration) -> Self {
+		Self {
+			source,
+			octaves: noise_config.octaves,
+			frequency: noise_config.freq,
+			lacunarity: noise_config.lacunarity,
+			gain: noise_config.gain,
+			mode: noise_config.mode,
+			normalize: true,
+			warp: noise_config.warp,
+		}
 	}
 
-	return noise / freq_sum;	
+	pub fn set_octaves(self, octaves: u8) -> Self { Self { octaves, ..self } }
+	pub fn set_frequency(self, frequency: f64) -> Self { Self { frequency, ..self } }
+	pub fn set_lacunarity(self, lacunarity: f64) -> Self { Self { lacunarity, ..self } }
+	pub fn set_gain(self, gain: f64) -> Self { Self { gain, ..self } }
+	pub fn set_mode(self, mode: FractalMode) -> Self { Self { mode, ..self } }
+	pub fn set_normalize(self, normalize: bool) -> Self { Self { normalize, ..self } }
+	pub fn set_warp(self, warp: f64) -> Self { Self { warp, ..self } }
+}
+
+impl<T, const N: usize> NoiseFn<[f64; N]> for FractalNoise<T> where T: NoiseFn<[f64; N]> {
+	fn get(&self, point: [f64; N]) -> f64 {
+		let mut point = point.map(|value| value * self.frequency);
+
+		if self.warp != 0.0 {
+			let offsets: [f64; N] = std::array::from_fn(|axis| {
+				let shift = WARP_OFFSETS[axis % WARP_OFFSETS.len()];
+				self.source.get(point.map(|value| value + shift))
+			});
+			for axis in 0..N {
+				point[axis] += offsets[axis] * self.warp;
+			}
+		}
+
+		let mut noise = 0.0;
+		let mut amplitude = 1.0;
+		let mut amplitude_sum = 0.0;
+		let mut ridge_weight = 1.0;
+
+		for _ in 0..self.octaves {
+			let sample = self.source.get(point);
+			let value = match self.mode {
+				FractalMode::Fbm => sample,
+				FractalMode::Billow => f64::abs(sample) * 2.0 - 0.5,
+				FractalMode::Ridged => {
+					let ridge = (1.0 - f64::abs(sample) * 2.0).max(0.0);
+					let ridge = ridge * ridge * ridge_weight;
+					ridge_weight = (ridge * 2.0).clamp(0.0, 1.0);
+					ridge - 0.5
+				},
+			};
+
+			noise += value * amplitude;
+			amplitude_sum += amplitude;
+			amplitude *= self.gain;
+			point = point.map(|value| value * self.lacunarity);
+		}
+
+		if self.normalize && amplitude_sum > 0.0 { noise / amplitude_sum } else { noise }
+	}
+}
+
+pub fn sample_noise_01(layer: &NoiseLayer, point: [f64;3]) -> f64 {
+	layer.get(point) + 0.5
+}
+
+pub fn sample_noise_vec2_01(layer: &NoiseLayer, x: f64, z: f64) -> f64 {
+	layer.get([x, z]) + 0.5
 }
@@ -0,0 +1,110 @@
+use bevy_inspector_egui::Inspectable;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::common::types::*;
+use crate::common::blocks::{BlockId, BlockRegistry};
+use crate::common::content::{ContentError, load_ron, load_required};
+use crate::common::voxels::voxel_helpers;
+use crate::generation::noise::get_chunk_rng;
+
+pub const ORE_REGISTRY_PATH: &str = "ores.ron";
+
+#[derive(Clone, Default, Inspectable, Deserialize)]
+pub struct OreConfiguration {
+	pub block: String,
+	// derived from the world seed, see ConfigurationState::apply_world_seed
+	#[serde(default)]
+	pub seed: i32,
+
+	// veins only start between these world heights
+	pub y_range: (i64, i64),
+	// the smallest and largest number of blocks a vein tries to place
+	pub vein_size: (u8, u8),
+	#[inspectable(min = 0, max = 64)]
+	pub veins_per_chunk: u8,
+	// the blocks a vein is allowed to replace, everything else is left alone
+	pub replaces: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct OreFile {
+	ores: Vec<OreConfiguration>,
+}
+
+pub fn load_ores(path: &str, registry: &BlockRegistry) -> Result<Vec<OreConfiguration>, ContentError> {
+	let file: OreFile = load_ron(path)?;
+	for ore in &file.ores {
+		ResolvedOre::new(ore, registry)?;
+	}
+	Ok(file.ores)
+}
+
+pub fn load_default_ores(registry: &BlockRegistry) -> Vec<OreConfiguration> {
+	load_required(ORE_REGISTRY_PATH, |path| load_ores(path, registry))
+}
+
+/// An ore with its block names looked up in the block registry
+#[derive(Clone)]
+pub struct ResolvedOre {
+	pub config: OreConfiguration,
+	pub block: BlockId,
+	pub replaces: Vec<BlockId>,
+}
+
+impl ResolvedOre {
+	pub fn new(ore: &OreConfiguration, registry: &BlockRegistry) -> Result<Self, ContentError> {
+		Ok(Self {
+			config: ore.clone(),
+			block: registry.resolve(&ore.block)?,
+			replaces: ore.replaces.iter().map(|name| registry.resolve(name)).collect::<Result<_, ContentError>>()?,
+		})
+	}
+}
+
+const VEIN_STEPS: [(i64, i64, i64); 6] = [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)];
+
+/// Scatters ore veins through the chunk. Every ore gets its own generator seeded from the chunk
+/// coordinates, so a chunk always gets the same veins no matter when it is generated
+pub fn place_ores(coords: Vector3, voxels: &mut VoxelCollection, ores: &[ResolvedOre]) {
+	let chunk_x = coords.x as i64;
+	let chunk_y = coords.y as i64;
+	let chunk_z = coords.z as i64;
+
+	let min_y = chunk_y * CHUNK_DIMENSIONS.y;
+	let max_y = min_y + CHUNK_DIMENSIONS.y;
+
+	for resolved in ores {
+		let ore = &resolved.config;
+
+		// the part of the ore's range that falls in this chunk
+		let start_min_y = ore.y_range.0.max(min_y);
+		let start_max_y = ore.y_range.1.min(max_y - 1);
+		if start_min_y > start_max_y { continue }
+
+		let mut rng = get_chunk_rng(ore.seed, chunk_x, chunk_y, chunk_z);
+
+		for _ in 0..ore.veins_per_chunk {
+			let mut x = rng.gen_range(0..CHUNK_DIMENSIONS.x);
+			let mut y = rng.gen_range(start_min_y..=start_max_y) - min_y;
+			let mut z = rng.gen_range(0..CHUNK_DIMENSIONS.z);
+			let size = rng.gen_range(ore.vein_size.0..=ore.vein_size.1.max(ore.vein_size.0));
+
+			// random walk from the start, the parts of a vein that wander out of the chunk are dropped
+			for _ in 0..size {
+				if (0..CHUNK_DIMENSIONS.x).contains(&x) && (0..CHUNK_DIMENSIONS.y).contains(&y) && (0..CHUNK_DIMENSIONS.z).contains(&z) {
+					let index = voxel_helpers::get_index(x as u16, y as u16, z as u16);
+					let voxel = &mut voxels[index];
+					if voxel.is_filled() && resolved.replaces.contains(&voxel.block_type()) {
+						voxel.set_block_type(resolved.block);
+					}
+				}
+
+				let (step_x, step_y, step_z) = VEIN_STEPS[rng.gen_range(0..VEIN_STEPS.len())];
+				x += step_x;
+				y += step_y;
+				z += step_z;
+			}
+		}
+	}
+}
@@ -0,0 +1,44 @@
+use bevy::prelude::{FromWorld, World};
+
+use crate::ConfigurationState;
+use crate::common::blocks::{BlockId, BlockRegistry};
+use crate::common::content::ContentError;
+use crate::generation::biomes::{BiomeLookup, ResolvedBiome, VariantBlocks, resolve_blocks};
+use crate::generation::ores::ResolvedOre;
+use crate::generation::structures::ResolvedStructure;
+
+/// Every block and biome name in the configuration looked up. It's rebuilt on the main thread whenever
+/// the configuration changes, so generation never runs into a name that isn't registered
+#[derive(Clone)]
+pub struct ResolvedConfiguration {
+	pub water: BlockId,
+	pub biomes: Vec<ResolvedBiome>,
+	pub biome_lookup: BiomeLookup,
+	pub ores: Vec<ResolvedOre>,
+	pub structures: Vec<ResolvedStructure>,
+	pub river_bed_blocks: VariantBlocks,
+}
+
+impl ResolvedConfiguration {
+	pub fn new(config: &ConfigurationState, registry: &BlockRegistry) -> Result<Self, ContentError> {
+		Ok(Self {
+			water: registry.id("water"),
+			biomes: config.biomes.iter().map(|biome| ResolvedBiome::new(biome, registry)).collect::<Result<_, ContentError>>()?,
+			biome_lookup: BiomeLookup::new(&config.biomes, &config.climate_table, &config.ocean_biome)?,
+			ores: config.ores.iter().map(|ore| ResolvedOre::new(ore, registry)).collect::<Result<_, ContentError>>()?,
+			structures: config.structures.iter().map(|structure| ResolvedStructure::new(structure, registry)).collect::<Result<_, ContentError>>()?,
+			river_bed_blocks: resolve_blocks(&config.river_configuration.bed_blocks, registry)?,
+		})
+	}
+}
+
+impl FromWorld for ResolvedConfiguration {
+	fn from_world(world: &mut World) -> Self {
+		let config = world.resource::<ConfigurationState>();
+		let registry = world.resource::<BlockRegistry>();
+		match ResolvedConfiguration::new(config, registry) {
+			Ok(resolved) => resolved,
+			Err(err) => panic!("the default configuration names a block or biome that doesn't exist: {:?}", err),
+		}
+	}
+}
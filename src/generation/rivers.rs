@@ -0,0 +1,48 @@
+use crate::ConfigurationState;
+use crate::generation::noise::{NoiseSources, sample_noise_vec2_01};
+
+/// The shape of a column that a river runs through
+pub struct RiverColumn {
+	pub surface_height: i64,
+	pub water_level: i64,
+	// inside the channel the bed blocks are used and the water is filled in, outside it's just the valley sides
+	pub in_channel: bool,
+	// how much of the 3d density noise to keep, 0 in the channel so nothing hangs over the water
+	pub density_scale: f64,
+}
+
+/// Rivers run along the middle of a ridged noise, where the noise is close to 0.5. The terrain is
+/// lowered into a valley around them, and the channel is cut below the water level
+pub fn get_river_column(sources: &NoiseSources, config: &ConfigurationState, x: f64, z: f64, surface_height: i64) -> Option<RiverColumn> {
+	let rivers = &config.river_configuration;
+	let sea_level = config.sea_level as i64;
+	if !rivers.enabled || surface_height < sea_level { return None }
+
+	let ridge = f64::abs(sample_noise_vec2_01(&sources.layers.rivers, x, z) * 2.0 - 1.0);
+	if ridge >= rivers.width + rivers.valley_width { return None }
+
+	// in the mountains the river stays in a gorge instead of cutting all the way down to the sea
+	let valley_floor = (surface_height - rivers.max_valley_depth as i64).max(sea_level);
+	let water_level = valley_floor - 1;
+
+	if ridge < rivers.width {
+		let center = 1.0 - ridge / rivers.width;
+		let bed_depth = 1.0 + (rivers.channel_depth - 1.0).max(0.0) * f64::sqrt(center);
+		return Some(RiverColumn {
+			surface_height: water_level - bed_depth as i64,
+			water_level,
+			in_channel: true,
+			density_scale: 0.0,
+		});
+	}
+
+	// smoothstep up the valley sides
+	let t = ((ridge - rivers.width) / rivers.valley_width.max(f64::EPSILON)).clamp(0.0, 1.0);
+	let t = t * t * (3.0 - 2.0 * t);
+	Some(RiverColumn {
+		surface_height: valley_floor + ((surface_height - valley_floor) as f64 * t) as i64,
+		water_level,
+		in_channel: false,
+		density_scale: t,
+	})
+}
@@ -0,0 +1,45 @@
+// FNV-1a, picked because it's tiny and gives the same hash on every platform and rust version
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+	bytes.into_iter().fold(FNV_OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Numbers are used as they are so a seed can be typed in directly, anything else is hashed
+pub fn get_world_seed(world_seed: &str) -> u64 {
+	let world_seed = world_seed.trim();
+	match world_seed.parse::<i64>() {
+		Ok(seed) => seed as u64,
+		Err(_) => fnv1a(world_seed.bytes()),
+	}
+}
+
+/// The seed for a single layer of generation, every layer gets its own seed from the one world seed
+pub fn derive_seed(world_seed: u64, layer: &str) -> i32 {
+	let hash = fnv1a(world_seed.to_le_bytes().into_iter().chain(layer.bytes()));
+	(hash ^ (hash >> 32)) as i32
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// a saved world is only reproducible while these stay the same, changing the hash or how layers
+	// are named moves every seed
+	#[test]
+	fn derived_seeds_are_stable() {
+		let world_seed = get_world_seed("12345");
+		assert_eq!(world_seed, 12345);
+		assert_eq!(derive_seed(world_seed, "height"), -615018542);
+		assert_eq!(derive_seed(world_seed, "caves/worms"), 2027886828);
+		assert_eq!(derive_seed(world_seed, "structures/oak_tree"), -511356426);
+		assert_eq!(derive_seed(world_seed, "structures/hut"), 345307812);
+	}
+
+	#[test]
+	fn text_seeds_are_hashed() {
+		assert_eq!(get_world_seed("forest"), 2348649809174312576);
+		assert_eq!(get_world_seed(" forest "), get_world_seed("forest"));
+	}
+}
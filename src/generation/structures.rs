@@ -0,0 +1,197 @@
+use bevy::prelude::IVec3;
+use bevy_inspector_egui::Inspectable;
+use rand::{Rng, rngs::StdRng};
+use serde::Deserialize;
+
+use crate::common::types::*;
+use crate::common::blocks::{BlockId, BlockRegistry};
+use crate::common::content::{ContentError, load_ron, load_required};
+use crate::common::storage::VoxelStorage;
+use crate::common::positions::*;
+use crate::common::voxels::{Facing, voxel_helpers};
+use crate::generation::noise::get_chunk_rng;
+
+pub const STRUCTURE_REGISTRY_PATH: &str = "structures.ron";
+
+#[derive(Copy, Clone, Default, PartialEq, Inspectable, Deserialize)]
+pub enum StructureKind {
+	#[default]
+	Tree,
+	Boulder,
+	Hut,
+}
+
+#[derive(Clone, Default, Inspectable, Deserialize)]
+pub struct StructureConfiguration {
+	// names the structure's seed, so it has to be unique
+	pub name: String,
+	pub kind: StructureKind,
+	// derived from the world seed, see ConfigurationState::apply_world_seed
+	#[serde(default)]
+	pub seed: i32,
+
+	#[inspectable(min = 0, max = 64)]
+	pub attempts_per_chunk: u8,
+	// chance each attempt places the structure, if it found somewhere to stand
+	#[inspectable(min = 0.0, max = 1.0)]
+	pub chance: f64,
+	// the surface blocks the structure can be built on
+	pub on_blocks: Vec<String>,
+
+	// trees use these for the trunk and leaves, boulders mix the two and huts use them for the walls and frame
+	pub primary_block: String,
+	pub secondary_block: String,
+}
+
+#[derive(Deserialize)]
+struct StructureFile {
+	structures: Vec<StructureConfiguration>,
+}
+
+pub fn load_structures(path: &str, registry: &BlockRegistry) -> Result<Vec<StructureConfiguration>, ContentError> {
+	let file: StructureFile = load_ron(path)?;
+	for (index, structure) in file.structures.iter().enumerate() {
+		if file.structures[..index].iter().any(|other| other.name == structure.name) {
+			return Err(ContentError::DuplicateName(structure.name.clone()));
+		}
+		ResolvedStructure::new(structure, registry)?;
+	}
+	Ok(file.structures)
+}
+
+pub fn load_default_structures(registry: &BlockRegistry) -> Vec<StructureConfiguration> {
+	load_required(STRUCTURE_REGISTRY_PATH, |path| load_structures(path, registry))
+}
+
+/// A structure with its block names looked up in the block registry
+#[derive(Clone)]
+pub struct ResolvedStructure {
+	pub config: StructureConfiguration,
+	pub on_blocks: Vec<BlockId>,
+	pub primary: BlockId,
+	pub secondary: BlockId,
+}
+
+impl ResolvedStructure {
+	pub fn new(structure: &StructureConfiguration, registry: &BlockRegistry) -> Result<Self, ContentError> {
+		Ok(Self {
+			config: structure.clone(),
+			on_blocks: structure.on_blocks.iter().map(|name| registry.resolve(name)).collect::<Result<_, ContentError>>()?,
+			primary: registry.resolve(&structure.primary_block)?,
+			secondary: registry.resolve(&structure.secondary_block)?,
+		})
+	}
+}
+
+/// Picks where the chunk's structures go and returns every block they're made of. Structures are
+/// seeded from the chunk's coordinates so they come out the same each time, and they can reach into
+/// neighbouring chunks
+pub fn get_structure_blocks(coords: Vector3Int, voxels: &VoxelStorage, structures: &[ResolvedStructure]) -> Vec<StructureBlock> {
+	let mut blocks = vec![];
+	let origin = ChunkPos(coords).origin();
+
+	for resolved in structures {
+		let structure = &resolved.config;
+		let (primary, secondary) = (resolved.primary, resolved.secondary);
+		let mut rng = get_chunk_rng(structure.seed, coords.x, coords.y, coords.z);
+
+		for _ in 0..structure.attempts_per_chunk {
+			let x = rng.gen_range(0..CHUNK_WIDTH as u16);
+			let z = rng.gen_range(0..CHUNK_WIDTH as u16);
+			if !rng.gen_bool(structure.chance.clamp(0.0, 1.0)) { continue }
+
+			let surface_y = match get_surface(voxels, x, z, &resolved.on_blocks) {
+				Some(y) => y,
+				None => continue,
+			};
+
+			let base = origin + IVec3::new(x as i32, surface_y as i32, z as i32);
+			match structure.kind {
+				StructureKind::Tree => add_tree(&mut blocks, &mut rng, base, primary, secondary),
+				StructureKind::Boulder => add_boulder(&mut blocks, &mut rng, base, primary, secondary),
+				StructureKind::Hut => add_hut(&mut blocks, base, primary, secondary),
+			}
+		}
+	}
+
+	blocks
+}
+
+// the highest voxel in the column that is one of the allowed blocks and has open air above it
+fn get_surface(voxels: &VoxelStorage, x: u16, z: u16, on_blocks: &[BlockId]) -> Option<u16> {
+	(0..CHUNK_HEIGHT as u16 - 1).rev().find(|&y| {
+		let voxel = voxels.get(voxel_helpers::get_index(x, y, z));
+		let above = voxels.get(voxel_helpers::get_index(x, y + 1, z));
+		matches!((voxel, above), (Some(voxel), Some(above))
+			if voxel.is_filled() && !above.is_filled() && on_blocks.contains(&voxel.block_type()))
+	})
+}
+
+fn add_tree(blocks: &mut Vec<StructureBlock>, rng: &mut StdRng, base: WorldPos, log: BlockId, leaves: BlockId) {
+	let trunk_height = rng.gen_range(4..=6);
+
+	for y in 1..=trunk_height {
+		blocks.push(StructureBlock { position: base + IVec3::new(0, y, 0), block: Some(log), replace: true, facing: Facing::Up });
+	}
+
+	// two wide layers around the top of the trunk and a small cap over it
+	for y in trunk_height - 1..=trunk_height + 1 {
+		let radius: i32 = if y > trunk_height { 1 } else { 2 };
+		for x in -radius..=radius {
+			for z in -radius..=radius {
+				// trim the corners so the canopy isn't a cube
+				if radius > 1 && x.abs() == radius && z.abs() == radius && rng.gen_bool(0.7) { continue }
+				blocks.push(StructureBlock { position: base + IVec3::new(x, y, z), block: Some(leaves), replace: false, facing: Facing::Up });
+			}
+		}
+	}
+}
+
+fn add_boulder(blocks: &mut Vec<StructureBlock>, rng: &mut StdRng, base: WorldPos, primary: BlockId, secondary: BlockId) {
+	let radius: f32 = rng.gen_range(1.0..2.5);
+	let extent = radius.ceil() as i32;
+
+	// sunk halfway into the ground
+	for x in -extent..=extent {
+		for y in -extent..=extent {
+			for z in -extent..=extent {
+				if (x * x + y * y + z * z) as f32 > radius * radius { continue }
+				let block = if rng.gen_bool(0.3) { secondary } else { primary };
+				blocks.push(StructureBlock { position: base + IVec3::new(x, y + extent / 2, z), block: Some(block), replace: false, facing: Facing::Up });
+			}
+		}
+	}
+}
+
+fn add_hut(blocks: &mut Vec<StructureBlock>, base: WorldPos, walls: BlockId, frame: BlockId) {
+	const HALF_WIDTH: i32 = 2;
+	const WALL_HEIGHT: i32 = 3;
+
+	for x in -HALF_WIDTH..=HALF_WIDTH {
+		for z in -HALF_WIDTH..=HALF_WIDTH {
+			let is_corner = x.abs() == HALF_WIDTH && z.abs() == HALF_WIDTH;
+			let is_wall = x.abs() == HALF_WIDTH || z.abs() == HALF_WIDTH;
+			let is_door = x == 0 && z == -HALF_WIDTH;
+
+			// fill under the floor so the hut doesn't float over a slope
+			for y in -2..0 {
+				blocks.push(StructureBlock { position: base + IVec3::new(x, y, z), block: Some(frame), replace: false, facing: Facing::Up });
+			}
+			blocks.push(StructureBlock { position: base + IVec3::new(x, 0, z), block: Some(walls), replace: true, facing: Facing::Up });
+
+			for y in 1..=WALL_HEIGHT {
+				let block = if is_corner {
+					Some(frame)
+				} else if is_wall && !(is_door && y <= 2) {
+					Some(walls)
+				} else {
+					None
+				};
+				blocks.push(StructureBlock { position: base + IVec3::new(x, y, z), block, replace: true, facing: Facing::Up });
+			}
+
+			// the roof beams are laid along x
+			blocks.push(StructureBlock { position: base + IVec3::new(x, WALL_HEIGHT + 1, z), block: Some(frame), replace: true, facing: Facing::Forward });
+		}
+	}
+}
@@ -5,7 +5,7 @@ use bevy::prelude::*;
 use bevy::render::camera::Projection;
 use bevy_egui::EguiPlugin;
 
-use crate::common::types::*;
+use crate::meshing::material::ChunkMaterial;
 use crate::systems::chunk_systems::*;
 
 pub mod common;
@@ -20,6 +20,7 @@ struct Moveable;
 #[derive(Default)]
 pub struct MaterialCache {
     chunk_material: Option<Handle<StandardMaterial>>,
+    greedy_chunk_material: Option<Handle<ChunkMaterial>>,
 }
 
 
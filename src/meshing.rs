@@ -1,3 +1,7 @@
 pub mod chunk;
 
-pub mod cubemeshes;
\ No newline at end of file
+pub mod cubemeshes;
+
+pub mod greedy;
+
+pub mod material;
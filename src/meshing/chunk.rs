@@ -1,38 +1,71 @@
 use crate::{common::types::*,
-	common::types::BlockType,
+	common::blocks::{BlockId, BlockRegistry},
+	common::storage::VoxelStorage,
 	systems::chunk_systems::ChunkState,
-	systems::chunk_systems::ChunkLookup
+	systems::chunk_systems::VoxelAccessError,
 };
 use crate::common::voxels::voxel_helpers;
+use crate::common::positions::*;
 use crate::meshing::cubemeshes::*;
+use crate::meshing::greedy::get_greedy_mesh_data;
+
+use bevy::prelude::{Component, FromWorld, IVec3};
+use bevy::utils::HashMap;
+use bevy_inspector_egui::Inspectable;
+
+// min and max corners of an atlas tile
+pub type UVRect = [[f32;2];2];
+
+pub const FACES: [u64;6] = [UP_FACE, DOWN_FACE, LEFT_FACE, RIGHT_FACE, FORWARD_FACE, BACKWARD_FACE];
+
+pub fn get_face_direction(face: u64) -> IVec3 {
+	match face {
+		UP_FACE => IVec3::Y,
+		DOWN_FACE => IVec3::NEG_Y,
+		LEFT_FACE => IVec3::Z,
+		RIGHT_FACE => IVec3::NEG_Z,
+		FORWARD_FACE => IVec3::X,
+		BACKWARD_FACE => IVec3::NEG_X,
+		_ => panic!("invalid mesh face {}", face),
+	}
+}
 
-use bevy::prelude::{Component, FromWorld };
-
-pub type UVArray = [[f32;2];4];
-
-pub fn get_uvs_for_block(uvs: & mut UVArray, block_type: BlockType) {
-	let grid_size = [4.0, 4.0];
-	match block_type {
-		BlockType::Grass		=> get_uvs(uvs, [0.0, 0.0], grid_size), 
-		BlockType::Snow			=> get_uvs(uvs, [0.0, 1.0], grid_size), 
-		BlockType::Sand			=> get_uvs(uvs, [0.0, 2.0], grid_size),
-		BlockType::Water		=> get_uvs(uvs, [0.0, 3.0], grid_size),
-		BlockType::Dirt			=> get_uvs(uvs, [1.0, 0.0], grid_size), 
-		BlockType::Stone		=> get_uvs(uvs, [1.0, 1.0], grid_size),
-		BlockType::Ice			=> get_uvs(uvs, [1.0, 2.0], grid_size), 
-		BlockType::DarkStone	=> get_uvs(uvs, [1.0, 3.0], grid_size),
+pub fn get_uvs_for_face(block_type: BlockId, face: u64, registry: &BlockRegistry) -> UVRect {
+	let grid_size = [registry.atlas_size.0 as f32, registry.atlas_size.1 as f32];
+	match registry.get(block_type) {
+		Some(block) => {
+			let tile = block.textures.get_tile(face);
+			get_uvs([tile.0 as f32, tile.1 as f32], grid_size)
+		},
+		None => panic!("no block registered for id {:?}", block_type),
 	}
 }
 
-fn get_uvs(uvs: & mut UVArray, coords: [f32;2], grid_size: [f32;2]) {
-	let tiny = 0.05;
+// how far in from the tile's edges to sample, as a fraction of the tile, so neighbouring tiles don't bleed in
+pub const TILE_INSET: f32 = 0.05;
+
+fn get_uvs(coords: [f32;2], grid_size: [f32;2]) -> UVRect {
 	let x_scale =  1.0 / grid_size[0];
 	let y_scale = 1.0 / grid_size[1];
 
-	uvs[0] = [coords[0] * x_scale + tiny, coords[1] * y_scale + tiny];
-	uvs[1] = [(coords[0] + 1.0) * x_scale - tiny, coords[1] * y_scale + tiny];
-	uvs[2] = [(coords[0]) * x_scale + tiny, (coords[1] + 1.0) * y_scale - tiny];
-	uvs[3] = [(coords[0] + 1.0) * x_scale - tiny, (coords[1] + 1.0) * y_scale - tiny];
+	[
+		[(coords[0] + TILE_INSET) * x_scale, (coords[1] + TILE_INSET) * y_scale],
+		[(coords[0] + 1.0 - TILE_INSET) * x_scale, (coords[1] + 1.0 - TILE_INSET) * y_scale],
+	]
+}
+
+// project the vertex onto the face so the top of the tile always points up on side faces
+fn get_face_uv(face: u64, vertex: [f32;3], uvs: &UVRect) -> [f32;2] {
+	let (u, v) = match face {
+		UP_FACE | DOWN_FACE => (vertex[0], vertex[2]),
+		LEFT_FACE | RIGHT_FACE => (vertex[0], 1.0 - vertex[1]),
+		_ => (vertex[2], 1.0 - vertex[1]),
+	};
+
+	[
+		uvs[0][0] + u * (uvs[1][0] - uvs[0][0]),
+		uvs[0][1] + v * (uvs[1][1] - uvs[0][1]),
+	]
 }
 
 
@@ -52,12 +85,12 @@ impl FromWorld for VoxelFaceEdges {
 
 fn get_voxel_edges() -> [Vec<(usize, usize)>;4] {
 	let mut voxels = [vec![], vec![], vec![], vec![]];
-	let max_x = 15;
-	let max_z = 15;
+	let max_x = CHUNK_WIDTH as u16 - 1;
+	let max_z = CHUNK_WIDTH as u16 - 1;
 
 	// get indicies for both sies of the Z seam
-	for y in 0..128 {
-		for x in 0..16 {
+	for y in 0..CHUNK_HEIGHT as u16 {
+		for x in 0..CHUNK_WIDTH as u16 {
 			// positions are relative to the seam
 			let left_index = voxel_helpers::get_index(x, y, max_z);
 			let right_index = voxel_helpers::get_index(x, y, 0);
@@ -67,8 +100,8 @@ fn get_voxel_edges() -> [Vec<(usize, usize)>;4] {
 	}
 
 	// get indicies for boths sides of the X seam
-	for y in 0..128 {
-		for z in 0..16 {
+	for y in 0..CHUNK_HEIGHT as u16 {
+		for z in 0..CHUNK_WIDTH as u16 {
 			let forward_index = voxel_helpers::get_index(0, y, z);
 			// ---- seam +x ^
 			let backward_index = voxel_helpers::get_index(max_x, y, z);
@@ -80,39 +113,43 @@ fn get_voxel_edges() -> [Vec<(usize, usize)>;4] {
 	voxels
 }
 
-pub fn get_mesh_data(face_data: &Vec<(usize, u8, BlockType)>, cube_data: &CubeMeshData) -> MeshData {
+pub fn get_mesh_data(face_data: &Vec<(usize, u8, Voxel)>, cube_data: &CubeMeshData, registry: &BlockRegistry) -> MeshData {
 	let mut mesh_data = get_mesh_data_container();
 
 	let mut faces_res;
 	let mut coords;
 	let mut vertex_index;
 	let mut adjusted: [f32;3] = [0.0, 0.0, 0.0];
-	let mut uvs :UVArray = [[0.0, 0.0];4];
-	for (index, key, block_type) in face_data {
-		get_uvs_for_block(&mut uvs, block_type.clone().try_into().unwrap());
-
-		faces_res = cube_data.cubes.get(key);
-		match faces_res {
-			Some(faces) => {
-				// TODO: Improve perf here
-				coords = voxel_helpers::get_coords_as_vec3(*index as Voxel);
-				vertex_index = mesh_data.verticies.len() as u32;
-
-				for i in 0..faces.vertex_count as usize {
-					// adjust verticies
-					adjusted[0] = faces.verticies[i][0] + coords.x as f32;
-					adjusted[1] = faces.verticies[i][1] + coords.y as f32;
-					adjusted[2] = faces.verticies[i][2] + coords.z as f32;
-					mesh_data.uvs.push(uvs[i % uvs.len()]); 
-					mesh_data.verticies.push(adjusted);
-				}
-
-				for i in 0..faces.normals.len() { mesh_data.normals.push(faces.normals[i]); }
-				for i in 0..faces.indicies.len() { mesh_data.indicies.push(faces.indicies[i] + vertex_index); }
-			},
-			None => panic!("invalid mesh face {}", key),
+	let mut uvs: UVRect;
+	for (index, key, voxel) in face_data {
+		// TODO: Improve perf here
+		coords = voxel_helpers::get_coords_from_index(*index);
+
+		for face in FACES {
+			if (*key as u64) & face == 0 { continue }
+
+			uvs = get_uvs_for_face(voxel.block_type(), voxel.facing().get_texture_face(face), registry);
+
+			faces_res = cube_data.cubes.get(&(face as u8));
+			match faces_res {
+				Some(faces) => {
+					vertex_index = mesh_data.verticies.len() as u32;
+
+					for i in 0..faces.vertex_count as usize {
+						// adjust verticies
+						adjusted[0] = faces.verticies[i][0] + coords.x as f32;
+						adjusted[1] = faces.verticies[i][1] + coords.y as f32;
+						adjusted[2] = faces.verticies[i][2] + coords.z as f32;
+						mesh_data.uvs.push(get_face_uv(face, faces.verticies[i], &uvs));
+						mesh_data.verticies.push(adjusted);
+					}
+
+					for i in 0..faces.normals.len() { mesh_data.normals.push(faces.normals[i]); }
+					for i in 0..faces.indicies.len() { mesh_data.indicies.push(faces.indicies[i] + vertex_index); }
+				},
+				None => panic!("invalid mesh face {}", face),
+			}
 		}
-
 	}
 
 	mesh_data
@@ -122,12 +159,12 @@ pub fn run_first_pass_meshing(voxels: &mut VoxelCollection) {
 	for index in 0..voxels.len() {
 			let voxel = voxels[index];
 
-            let coords = voxel_helpers::get_coords_as_voxel_coords(voxel);
+            let coords = voxel.coords();
 			let x = coords.x;
 			let y = coords.y;
 			let z = coords.z;
 
-            if !voxel_helpers::is_filled(voxel)
+            if !voxel.is_filled()
             {
 				continue;
             }
@@ -139,11 +176,11 @@ pub fn run_first_pass_meshing(voxels: &mut VoxelCollection) {
 			let mut target_voxel;
 
             // don't mesh the top of the world
-            if y < 127
+            if y < CHUNK_HEIGHT as u16 - 1
             {
                 target_index = voxel_helpers::get_index(x, y + 1, z);
 				target_voxel = voxels[target_index];
-				if voxel_helpers::should_create_face(voxel, target_voxel) { key |= 0b1; }
+				if voxel.should_create_face(target_voxel) { key |= 0b1; }
             }
 
             // don't mesh the bottom face of the world
@@ -151,13 +188,13 @@ pub fn run_first_pass_meshing(voxels: &mut VoxelCollection) {
             {
                 target_index = voxel_helpers::get_index(x, y - 1, z);
 				target_voxel = voxels[target_index];
-				if voxel_helpers::should_create_face(voxel, target_voxel) { key |= 0b10; }
+				if voxel.should_create_face(target_voxel) { key |= 0b10; }
             }
-            if z < 15
+            if z < CHUNK_WIDTH as u16 - 1
             {
 				target_index = voxel_helpers::get_index(x, y, z + 1);
 				target_voxel = voxels[target_index];
-				if voxel_helpers::should_create_face(voxel, target_voxel) { key |= 0b100; }
+				if voxel.should_create_face(target_voxel) { key |= 0b100; }
  
             }            
 
@@ -165,24 +202,24 @@ pub fn run_first_pass_meshing(voxels: &mut VoxelCollection) {
             {
                 target_index = voxel_helpers::get_index(x, y, z - 1);
 				target_voxel = voxels[target_index];
-				if voxel_helpers::should_create_face(voxel, target_voxel) { key |= 0b1000; }
+				if voxel.should_create_face(target_voxel) { key |= 0b1000; }
             }
 
-            if x < 15
+            if x < CHUNK_WIDTH as u16 - 1
             {
                 target_index = voxel_helpers::get_index(x + 1, y, z);
 				target_voxel = voxels[target_index];
-				if voxel_helpers::should_create_face(voxel, target_voxel) { key |= 0b10000; }
+				if voxel.should_create_face(target_voxel) { key |= 0b10000; }
             }
 
             if x > 0
             {
                 target_index = voxel_helpers::get_index(x - 1, y, z);
 				target_voxel = voxels[target_index];
-				if voxel_helpers::should_create_face(voxel, target_voxel) { key |= 0b100000; }
+				if voxel.should_create_face(target_voxel) { key |= 0b100000; }
             }
 
-            voxels[index] = voxel_helpers::set_mesh_data(voxel, key & 0xFF);
+            voxels[index].set_face_mask(key & 0xFF);
 	}
 }
 
@@ -199,109 +236,108 @@ pub fn update_edge_meshes(
 		let ours = our_voxels[ours_index.clone()];
 		let theirs = their_voxels[their_index.clone()];
 
-		if !voxel_helpers::is_filled(ours) { continue }
+		if !ours.is_filled() { continue }
 
-		let our_mesh_data = voxel_helpers::get_mesh_data(ours);
+		let our_mesh_data = ours.face_mask();
 
-		if voxel_helpers::should_create_face(ours, theirs) {
-			our_voxels[ours_index.clone()] = voxel_helpers::set_mesh_data(ours, our_mesh_data | our_face);
+		if ours.should_create_face(theirs) {
+			our_voxels[ours_index.clone()].set_face_mask(our_mesh_data | our_face);
 		} else {
-			our_voxels[ours_index.clone()] = voxel_helpers::set_mesh_data(ours, our_mesh_data & not_our_face);
+			our_voxels[ours_index.clone()].set_face_mask(our_mesh_data & not_our_face);
 		}
 	}	
 }
 
-fn get_chunk_and_coords(x: i16, y: i16, z: i16) -> (Vector3Int, usize) {
-	let mut direction = VECTOR3_INT_ZERO;
-	let mut results = (x as u16, y as u16, z as u16);
-	if x < 0 {
-		direction = direction + VECTOR3_INT_BACKWARD;
-		results.0 = (CHUNK_DIMENSIONS.x - 1) as u16;
-	} else if x == CHUNK_DIMENSIONS.x as i16 {
-		direction = direction + VECTOR3_INT_FORWARD;
-		results.0 = 0;
-	}
-	if y < 0 {
-		direction = direction + VECTOR3_INT_DOWN;
-		results.1 = (CHUNK_DIMENSIONS.y - 1) as u16;
-	} else if y == CHUNK_DIMENSIONS.y as i16 {
-		direction = direction + VECTOR3_INT_UP;
-		results.1 = 0;
+/// A copy of a chunk and the six chunks next to it, so the chunk can be meshed on another thread
+/// while the world keeps changing
+pub struct ChunkSnapshot {
+	pub coords: Vector3Int,
+	chunks: HashMap<Vector3Int, VoxelStorage>,
+}
+
+impl ChunkSnapshot {
+	/// Fails with ChunkNotGenerated if a neighbor needs to finish generating before the faces along its edge are known
+	pub fn new(coords: Vector3Int, state: &ChunkState) -> Result<Self, VoxelAccessError> {
+		let mut chunks = HashMap::default();
+
+		let ours = state.chunks.get(&coords).ok_or(VoxelAccessError::ChunkNotLoaded)?;
+		chunks.insert(coords, ours.voxels.clone());
+
+		for face in FACES {
+			let direction = get_face_direction(face);
+			let neighbor = coords + Vector3Int { x: direction.x as i64, y: direction.y as i64, z: direction.z as i64 };
+
+			if let Some(chunk_data) = state.chunks.get(&neighbor) {
+				chunks.insert(neighbor, chunk_data.voxels.clone());
+			} else if state.generating.contains(&neighbor) {
+				return Err(VoxelAccessError::ChunkNotGenerated);
+			}
+		}
+
+		Ok(Self { coords, chunks })
 	}
 
-	if z < 0 {
-		direction = direction + VECTOR3_INT_RIGHT;
-		results.2 = (CHUNK_DIMENSIONS.z - 1) as u16;
-	} else if z == CHUNK_DIMENSIONS.z as i16 {
-		direction = direction + VECTOR3_INT_LEFT;
-		results.2 = 0;
+	/// The chunk being meshed, without its neighbors
+	pub fn voxels(&self) -> Option<&VoxelStorage> {
+		self.chunks.get(&self.coords)
 	}
 
-	(direction, voxel_helpers::get_index(results.0, results.1, results.2))
+	pub fn get_world_voxel(&self, position: IVec3) -> Result<Voxel, VoxelAccessError> {
+		let (chunk, local) = WorldPos(position).split();
+		match self.chunks.get(&chunk.0) {
+			Some(voxels) => voxels.get(local.index()).ok_or(VoxelAccessError::OutOfBounds),
+			None => Err(VoxelAccessError::ChunkNotLoaded),
+		}
+	}
 }
+
 // TODO: Generate All Mesh Data Points At Once
-pub fn generate_mesh_raw_data(
-	chunk_coords: Vector3Int,
-	state: &ChunkState,
-) -> Vec<(usize, u8, BlockType)> {
+pub fn generate_mesh_raw_data(snapshot: &ChunkSnapshot, registry: &BlockRegistry) -> Vec<(usize, u8, Voxel)> {
 	let mut results = vec!();
+	let chunk_coords = snapshot.coords;
 
-	if let Some(our_chunk) = state.chunks.get(&chunk_coords) {
-		let our_voxels = &our_chunk.voxels;
-		for index in 0..our_voxels.len() {
+	if let Some(our_chunk) = snapshot.chunks.get(&chunk_coords) {
+		for (index, voxel) in our_chunk.iter().enumerate() {
 
-				if !voxel_helpers::is_filled(our_voxels[index])
+				if !voxel.is_filled()
 				{
 					continue;
 				}
 
 				let mut key = 0u64;
 
-				let voxel = our_voxels[index];
+				let position = WorldPos::from_parts(ChunkPos(chunk_coords), LocalPos(voxel.coords()));
 
-				let coords = voxel_helpers::get_coords_as_voxel_coords(voxel);
-				let x = coords.x as i16;
-				let y = coords.y as i16;
-				let z = coords.z as i16;
-
-
-				let up_voxel_info = get_chunk_and_coords(x, y +1, z);
-				let down_voxel_info = get_chunk_and_coords(x, y - 1, z);
-				let left_voxel_info = get_chunk_and_coords(x, y, z + 1);
-				let right_voxel_info = get_chunk_and_coords(x, y, z - 1);
-				let forward_voxel_info = get_chunk_and_coords(x + 1, y, z);
-				let backward_voxel_info = get_chunk_and_coords(x - 1, y, z);
-
-				if let Some(up_voxel) = state.get_voxel_by_index(chunk_coords + up_voxel_info.0, up_voxel_info.1) {
-					if voxel_helpers::should_create_face(voxel, up_voxel) { key |= 0b1; }
-				}
-
-				if let Some(down_voxel) = state.get_voxel_by_index(chunk_coords + down_voxel_info.0, down_voxel_info.1) {
-					if voxel_helpers::should_create_face(voxel, down_voxel) { key |= 0b10; }
-				}
-
-				if let Some(left_voxel) = state.get_voxel_by_index(chunk_coords + left_voxel_info.0, left_voxel_info.1) {
-					if voxel_helpers::should_create_face(voxel, left_voxel) { key |= 0b100; }
-				}
-
-				if let Some(right_voxel) = state.get_voxel_by_index(chunk_coords + right_voxel_info.0, right_voxel_info.1) {
-					if voxel_helpers::should_create_face(voxel, right_voxel) { key |= 0b1000; }
-				}
-
-				if let Some(forward_voxel) = state.get_voxel_by_index(chunk_coords + forward_voxel_info.0, forward_voxel_info.1) {
-					if voxel_helpers::should_create_face(voxel, forward_voxel) { key |= 0b10000; }
-				}
-
-				if let Some(backward_voxel) = state.get_voxel_by_index(chunk_coords + backward_voxel_info.0, backward_voxel_info.1) {
-					if voxel_helpers::should_create_face(voxel, backward_voxel) { key |= 0b100000; }
+				for face in FACES {
+					// missing neighbors are past the edge of the loaded world, leave those faces off
+					if let Ok(neighbor) = snapshot.get_world_voxel(position.0 + get_face_direction(face)) {
+						if registry.should_create_face(voxel, neighbor) { key |= face; }
+					}
 				}
 
 				if key != 0 {
-					results.push((index, (key & 0xFF) as u8, voxel_helpers::get_block_type(voxel).try_into().unwrap()));
+					results.push((index, (key & 0xFF) as u8, voxel));
 				}
 		}
 
 	}
 
 	results
-}
\ No newline at end of file
+}
+
+/// Which mesher builds the chunk meshes, switching re-renders every loaded chunk
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug, Inspectable)]
+pub enum MeshingMode {
+	// one quad for every visible face, the uvs point straight into the atlas
+	#[default]
+	PerFace,
+	// merges matching faces into bigger quads, needs the chunk material to repeat the texture across them
+	Greedy,
+}
+
+pub fn build_chunk_mesh(snapshot: &ChunkSnapshot, mode: MeshingMode, cube_data: &CubeMeshData, registry: &BlockRegistry) -> MeshData {
+	match mode {
+		MeshingMode::PerFace => get_mesh_data(&generate_mesh_raw_data(snapshot, registry), cube_data, registry),
+		MeshingMode::Greedy => get_greedy_mesh_data(snapshot, cube_data, registry),
+	}
+}
@@ -1,12 +1,15 @@
  use crate::common::types::*;
 use bevy::{prelude::*, utils::HashMap};
 
-pub const MAX_VERTICIES: usize = (16 * 16 * 128) / 2 * 24;
+pub const MAX_VERTICIES: usize = CHUNK_VOLUME / 2 * 24;
 
+#[derive(Clone)]
 pub struct MeshData {
 	pub verticies: Vec<[f32;3]>,
 	pub normals: Vec<[f32;3]>,
 	pub uvs: Vec<[f32;2]>,
+	// the atlas tile each vertex samples from, only the greedy mesher fills this in
+	pub tiles: Vec<[f32;2]>,
 	pub indicies: Vec<u32>,
 	pub vertex_count: u32,
 }
@@ -16,11 +19,13 @@ pub fn get_mesh_data_container() -> MeshData {
 		verticies: vec![],
 		normals: vec![],
 		uvs: vec![],
+		tiles: vec![],
 		indicies: vec![],
 		vertex_count: 0,
 	};
 }
 
+#[derive(Clone)]
 pub struct CubeMeshData {
 	pub cubes: HashMap<u8, MeshData>,
 }
@@ -0,0 +1,184 @@
+use crate::common::types::*;
+use crate::common::blocks::BlockRegistry;
+use crate::common::storage::VoxelStorage;
+use crate::common::voxels::voxel_helpers;
+use crate::common::positions::*;
+use crate::meshing::chunk::{ChunkSnapshot, FACES, get_face_direction};
+use crate::meshing::cubemeshes::*;
+
+use bevy::prelude::IVec3;
+
+// an atlas tile's column and row
+type Tile = (u8, u8);
+
+// the axis a face points along and the two axes across it, in the order get_face_uv projects them.
+// side faces flip v so the top of the tile points up
+fn get_face_axes(face: u64) -> (usize, usize, usize, bool) {
+	match face {
+		UP_FACE | DOWN_FACE => (1, 0, 2, false),
+		LEFT_FACE | RIGHT_FACE => (2, 0, 1, true),
+		_ => (0, 2, 1, true),
+	}
+}
+
+/// Meshes a chunk by merging neighboring faces that point the same way and use the same atlas tile
+/// into one quad. The uvs count blocks across the quad instead of pointing into the atlas, the chunk
+/// material wraps them inside the vertex's tile so the texture repeats once per block
+pub fn get_greedy_mesh_data(snapshot: &ChunkSnapshot, cube_data: &CubeMeshData, registry: &BlockRegistry) -> MeshData {
+	let mut mesh_data = get_mesh_data_container();
+	let voxels = match snapshot.voxels() {
+		Some(voxels) => voxels,
+		None => return mesh_data,
+	};
+
+	let size = [CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_WIDTH];
+
+	for face in FACES {
+		let unit_face = match cube_data.cubes.get(&(face as u8)) {
+			Some(unit_face) => unit_face,
+			None => panic!("invalid mesh face {}", face),
+		};
+
+		let (normal_axis, u_axis, v_axis, _) = get_face_axes(face);
+		let (width, height) = (size[u_axis], size[v_axis]);
+		let mut mask: Vec<Option<Tile>> = vec![None; width * height];
+
+		for slice in 0..size[normal_axis] {
+			for v in 0..height {
+				for u in 0..width {
+					let mut coords = [0u16; 3];
+					coords[normal_axis] = slice as u16;
+					coords[u_axis] = u as u16;
+					coords[v_axis] = v as u16;
+					mask[u + v * width] = get_visible_tile(snapshot, voxels, coords, face, registry);
+				}
+			}
+
+			// grow each quad as wide as the row allows, then down as many rows as match the whole width
+			for v in 0..height {
+				let mut u = 0;
+				while u < width {
+					let tile = match mask[u + v * width] {
+						Some(tile) => tile,
+						None => { u += 1; continue }
+					};
+
+					let mut quad_width = 1;
+					while u + quad_width < width && mask[u + quad_width + v * width] == Some(tile) {
+						quad_width += 1;
+					}
+
+					let mut quad_height = 1;
+					while v + quad_height < height
+						&& (u..u + quad_width).all(|x| mask[x + (v + quad_height) * width] == Some(tile)) {
+						quad_height += 1;
+					}
+
+					for y in v..v + quad_height {
+						for x in u..u + quad_width {
+							mask[x + y * width] = None;
+						}
+					}
+
+					add_quad(&mut mesh_data, unit_face, face, [slice, u, v], [quad_width, quad_height], tile);
+					u += quad_width;
+				}
+			}
+		}
+	}
+
+	mesh_data
+}
+
+// the tile the voxel shows on this face, or None when the face is hidden. faces against a missing
+// neighbor are left off the same as the per face mesher
+fn get_visible_tile(snapshot: &ChunkSnapshot, voxels: &VoxelStorage, coords: [u16;3], face: u64, registry: &BlockRegistry) -> Option<Tile> {
+	let voxel = voxels.get(voxel_helpers::get_index(coords[0], coords[1], coords[2]))?;
+	if !voxel.is_filled() { return None }
+
+	let position = ChunkPos(snapshot.coords).origin() + IVec3::new(coords[0] as i32, coords[1] as i32, coords[2] as i32);
+	let neighbor = snapshot.get_world_voxel(position.0 + get_face_direction(face)).ok()?;
+	if !registry.should_create_face(voxel, neighbor) { return None }
+
+	match registry.get(voxel.block_type()) {
+		Some(block) => Some(block.textures.get_tile(voxel.facing().get_texture_face(face))),
+		None => panic!("no block registered for id {:?}", voxel.block_type()),
+	}
+}
+
+// stretches the unit face across the quad, which keeps its winding and normals
+fn add_quad(mesh_data: &mut MeshData, unit_face: &MeshData, face: u64, start: [usize;3], size: [usize;2], tile: Tile) {
+	let (normal_axis, u_axis, v_axis, flip_v) = get_face_axes(face);
+	let [slice, u, v] = start;
+	let (quad_width, quad_height) = (size[0] as f32, size[1] as f32);
+	let vertex_index = mesh_data.verticies.len() as u32;
+
+	for vertex in &unit_face.verticies {
+		let mut position = *vertex;
+		position[normal_axis] += slice as f32;
+		position[u_axis] = u as f32 + vertex[u_axis] * quad_width;
+		position[v_axis] = v as f32 + vertex[v_axis] * quad_height;
+
+		let tile_v = if flip_v { 1.0 - vertex[v_axis] } else { vertex[v_axis] };
+
+		mesh_data.verticies.push(position);
+		mesh_data.uvs.push([vertex[u_axis] * quad_width, tile_v * quad_height]);
+		mesh_data.tiles.push([tile.0 as f32, tile.1 as f32]);
+	}
+
+	mesh_data.normals.extend_from_slice(&unit_face.normals);
+	mesh_data.indicies.extend(unit_face.indicies.iter().map(|index| index + vertex_index));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::common::blocks::BLOCK_REGISTRY_PATH;
+	use crate::systems::chunk_systems::ChunkState;
+	use bevy::prelude::{FromWorld, World};
+	use bevy::utils::HashSet;
+
+	// meshes a chunk with no neighbors loaded, filling a one block thick slab from x_blocks, keeping
+	// a block of air around it so none of its faces are left off at the chunk's edges
+	fn mesh_slab(x_blocks: impl Fn(u16) -> &'static str) -> MeshData {
+		let registry = BlockRegistry::load(BLOCK_REGISTRY_PATH).unwrap();
+		let cube_data = CubeMeshData::from_world(&mut World::new());
+
+		let mut voxels = vec![Voxel::default(); CHUNK_VOLUME];
+		for x in 1..CHUNK_WIDTH as u16 - 1 {
+			for z in 1..CHUNK_WIDTH as u16 - 1 {
+				let mut voxel = Voxel::default();
+				voxel.set_filled(true);
+				voxel.set_block_type(registry.id(x_blocks(x)));
+				voxels[voxel_helpers::get_index(x, 10, z)] = voxel;
+			}
+		}
+
+		let coords = Vector3Int { x: 0, y: 0, z: 0 };
+		let mut state = ChunkState::default();
+		state.chunks.insert(coords, ChunkData { voxels: VoxelStorage::from_voxels(&voxels), ..Default::default() });
+
+		let snapshot = ChunkSnapshot::new(coords, &state).unwrap();
+		get_greedy_mesh_data(&snapshot, &cube_data, &registry)
+	}
+
+	#[test]
+	fn flat_slab_is_one_quad_per_face() {
+		let mesh_data = mesh_slab(|_| "stone");
+		assert_eq!(mesh_data.verticies.len(), 6 * 4);
+		assert_eq!(mesh_data.indicies.len(), 6 * 6);
+	}
+
+	#[test]
+	fn different_tiles_do_not_merge() {
+		let mesh_data = mesh_slab(|x| if x < 8 { "stone" } else { "dirt" });
+
+		// the top, bottom and both long sides split in two, the ends are one block each
+		assert_eq!(mesh_data.verticies.len(), 10 * 4);
+
+		let registry = BlockRegistry::load(BLOCK_REGISTRY_PATH).unwrap();
+		let tile = |name: &str| registry.get(registry.id(name)).unwrap().textures.side;
+		let tiles: HashSet<Tile> = mesh_data.tiles.iter().map(|tile| (tile[0] as u8, tile[1] as u8)).collect();
+		assert_eq!(tiles, HashSet::from_iter([tile("stone"), tile("dirt")]));
+	}
+}
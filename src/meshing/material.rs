@@ -0,0 +1,47 @@
+use bevy::pbr::{MaterialPipeline, MaterialPipelineKey};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::mesh::{MeshVertexAttribute, MeshVertexBufferLayout};
+use bevy::render::render_resource::{AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError, VertexFormat};
+
+/// The atlas tile a vertex samples from, set by the greedy mesher
+pub const ATTRIBUTE_ATLAS_TILE: MeshVertexAttribute = MeshVertexAttribute::new("Vertex_AtlasTile", 988540917, VertexFormat::Float32x2);
+
+/// Draws greedy meshed chunks. A merged quad's uvs run past 1.0, so the shader wraps them back
+/// into the vertex's atlas tile instead of sampling the atlas directly
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "6c1f2a4e-8d3b-4f5a-9e27-3b8c0d41a7f2"]
+pub struct ChunkMaterial {
+	#[texture(0)]
+	#[sampler(1)]
+	pub atlas: Handle<Image>,
+	// columns and rows of tiles in the atlas
+	#[uniform(2)]
+	pub atlas_size: Vec2,
+}
+
+impl Material for ChunkMaterial {
+	fn vertex_shader() -> ShaderRef {
+		"shaders/chunk.wgsl".into()
+	}
+
+	fn fragment_shader() -> ShaderRef {
+		"shaders/chunk.wgsl".into()
+	}
+
+	fn specialize(
+		_pipeline: &MaterialPipeline<Self>,
+		descriptor: &mut RenderPipelineDescriptor,
+		layout: &MeshVertexBufferLayout,
+		_key: MaterialPipelineKey<Self>,
+	) -> Result<(), SpecializedMeshPipelineError> {
+		let vertex_layout = layout.get_layout(&[
+			Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+			Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+			Mesh::ATTRIBUTE_UV_0.at_shader_location(2),
+			ATTRIBUTE_ATLAS_TILE.at_shader_location(3),
+		])?;
+		descriptor.vertex.buffers = vec![vertex_layout];
+		Ok(())
+	}
+}
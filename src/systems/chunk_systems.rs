@@ -1,16 +1,24 @@
-use bevy::{prelude::*, utils::{HashMap, HashSet}, render::mesh};
+use std::sync::Arc;
+
+use bevy::{prelude::*, utils::{HashMap, HashSet}, render::mesh, tasks::{AsyncComputeTaskPool, Task}};
 use bevy_inspector_egui::{Inspectable, egui};
+use futures_lite::future;
+use serde::Deserialize;
 
 use crate::{
     common::{
         types::*,
-        voxels::voxel_helpers
+        voxels::voxel_helpers,
+        blocks::{BlockId, BlockRegistry},
+        storage::VoxelStorage,
+        positions::*,
     }, 
     meshing::{
         chunk::*,
-        cubemeshes::CubeMeshData,
+        cubemeshes::{CubeMeshData, MeshData},
+        material::{ATTRIBUTE_ATLAS_TILE, ChunkMaterial},
     },
-    generation::chunks, MaterialCache
+    generation::{chunks, noise::{FractalMode, NoiseSources}, seeds::{derive_seed, get_world_seed}, biomes::{BiomeConfiguration, LayerBlock, load_default_biomes}, ores::{OreConfiguration, load_default_ores}, structures::{self, StructureConfiguration, load_default_structures}, resolved::ResolvedConfiguration}, MaterialCache
 };
 
 use bevy_inspector_egui::InspectorPlugin;
@@ -53,6 +61,8 @@ impl Default for MeshReference {
 pub struct ChunkState {
     pub chunks_load: Vec<Vector3Int>,
     pub chunks: HashMap<Vector3Int, ChunkData>,
+    // chunks that have been spawned but haven't been generated yet
+    pub generating: HashSet<Vector3Int>,
     pub center: Vector3Int,
 }
 
@@ -64,21 +74,32 @@ impl Plugin for ChunkPlugin {
             .add_event::<FluidUpdateEvent>()
             .add_startup_system(setup)
             .add_system(queue_new_chunks)
-            .add_system(generator.after(queue_new_chunks))
-            .add_system(generate_full_edge_meshes.after(generator))
+            .add_system(update_noise_sources)
+            .add_system(update_resolved_configuration.after(update_noise_sources))
+            .add_system(generator.after(queue_new_chunks).after(update_resolved_configuration))
+            .add_system(poll_generation_tasks.after(generator))
+            .add_system(generate_structures.after(poll_generation_tasks))
+            .add_system(generate_full_edge_meshes.after(generate_structures))
             .add_system(spawn_random_blocks.after(generate_full_edge_meshes))
             .add_system(fluid_update_system.after(generate_full_edge_meshes))
             .add_system(fluid_update_event_processor.after(fluid_update_system))
             .add_system(handle_set_block_type_events.after(fluid_update_event_processor))
-            .add_system(render_chunk.after(generate_full_edge_meshes))
+            .add_system(apply_meshing_mode)
+            // finished meshes are taken before new tasks go in, so a chunk's new task isn't removed along with its old one
+            .add_system(upload_chunk_meshes.after(apply_meshing_mode))
+            .add_system(render_chunk.after(generate_full_edge_meshes).after(upload_chunk_meshes))
             .add_system(reload_chunk.after(render_chunk))
             .add_stage_after(CoreStage::Last, SystemStages::Cleanup, SystemStage::parallel())
             .add_system_to_stage(SystemStages::Cleanup, manage_loaded_chunk)
+            .init_resource::<BlockRegistry>()
             .init_resource::<CubeMeshData>()
             .init_resource::<ConfigurationState>()
+            .init_resource::<ResolvedConfiguration>()
+            .init_resource::<NoiseSources>()
             .init_resource::<VoxelFaceEdges>()
             .init_resource::<ChunkState>()
             .init_resource::<MaterialCache>()
+            .add_plugin(MaterialPlugin::<ChunkMaterial>::default())
             .add_plugin(InspectorPlugin::<ConfigurationState>::new());
 
     }
@@ -86,9 +107,11 @@ impl Plugin for ChunkPlugin {
 
 fn setup(
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut chunk_materials: ResMut<Assets<ChunkMaterial>>,
     mut state: ResMut<ChunkState>,
     mut material_cache: ResMut<MaterialCache>,
     config: Res<ConfigurationState>,
+    registry: Res<BlockRegistry>,
     asset_server: Res<AssetServer>,
 ) {
 
@@ -97,78 +120,148 @@ fn setup(
     let chunk_material = materials.add(StandardMaterial {
         metallic: 0.0,
         reflectance: 0.0,
-        base_color_texture : Option::Some(texture_handle),
+        base_color_texture : Option::Some(texture_handle.clone()),
         ..default()
     });
 
+    let greedy_chunk_material = chunk_materials.add(ChunkMaterial {
+        atlas: texture_handle,
+        atlas_size: Vec2::new(registry.atlas_size.0 as f32, registry.atlas_size.1 as f32),
+    });
+
     material_cache.chunk_material = Some(chunk_material);
+    material_cache.greedy_chunk_material = Some(greedy_chunk_material);
 
-    let center = state.center;
+    queue_chunks_in_range(&mut state, &config);
+}
+
+fn get_chunk_distance_squared(a: Vector3Int, b: Vector3Int) -> i64 {
+    let offset = a - b;
+    let x = offset.x * CHUNK_DIMENSIONS.x;
+    let y = offset.y * CHUNK_DIMENSIONS.y;
+    let z = offset.z * CHUNK_DIMENSIONS.z;
+    x * x + y * y + z * z
+}
+
+// distance is in chunk widths and measured in 3d from the center, vertical_distance caps how many chunks above and below count
+fn is_chunk_in_range(coords: Vector3Int, center: Vector3Int, distance: i64, vertical_distance: i64) -> bool {
+    let radius = distance * CHUNK_DIMENSIONS.x;
+    get_chunk_distance_squared(coords, center) <= radius * radius && i64::abs(coords.y - center.y) <= vertical_distance
+}
+
+// a neighbour is only worth waiting for if it's inside the loading range, otherwise it never arrives
+fn is_neighbour_missing(state: &ChunkState, config: &ConfigurationState, coords: Vector3Int) -> bool {
+    !state.chunks.contains_key(&coords)
+        && is_chunk_in_range(coords, state.center, config.loading_distance as i64, config.vertical_loading_distance as i64)
+}
 
+fn queue_chunks_in_range(state: &mut ChunkState, config: &ConfigurationState) {
+    let center = state.center;
     let loading_distance = config.loading_distance as i64;
-    for x in 0-loading_distance..loading_distance {
-        for z in 0-loading_distance..loading_distance {
-            state.chunks_load.push(Vector3Int { x: x, y: 0, z: z } + center);
+    let vertical_loading_distance = config.vertical_loading_distance as i64;
+
+    // anything still queued from the old center that's now out of range would only be unloaded again
+    state.chunks_load.retain(|&coords| is_chunk_in_range(coords, center, loading_distance, vertical_loading_distance));
+    let mut queued: HashSet<Vector3Int> = state.chunks_load.iter().copied().collect();
+
+    for x in 0-loading_distance..=loading_distance {
+        for y in 0-vertical_loading_distance..=vertical_loading_distance {
+            for z in 0-loading_distance..=loading_distance {
+                let coords = Vector3Int { x, y, z } + center;
+                if !is_chunk_in_range(coords, center, loading_distance, vertical_loading_distance) { continue }
+
+                if !state.chunks.contains_key(&coords) && !state.generating.contains(&coords) && queued.insert(coords) {
+                    state.chunks_load.push(coords);
+                }
+            }
         }
     }
+
+    // chunks are popped off the end of the queue, so the closest ones need to be last
+    state.chunks_load.sort_by_key(|coords| std::cmp::Reverse(get_chunk_distance_squared(*coords, center)));
 }
 
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum VoxelAccessError {
+    // nothing has been spawned for the chunk, or it has been unloaded
+    ChunkNotLoaded,
+    // the chunk is spawned but its voxels haven't been generated yet
+    ChunkNotGenerated,
+    OutOfBounds,
+}
+
 pub trait ChunkLookup {
-    fn get_voxel(&self, chunk_coords: Vector3Int, voxel_coords: VoxelCoords) -> Option<Voxel>;
-    fn get_voxel_by_index(&self, chunk_coords: Vector3Int, voxel_index: usize) -> Option<Voxel>;
-    fn set_voxel(&mut self, chunk_coords: Vector3Int, voxel_coords: VoxelCoords, data: Voxel) -> Option<Voxel>;
-    fn set_voxel_by_index(&mut self, chunk_coords: Vector3Int, voxel_index: usize, data: Voxel) -> Option<Voxel>;
+    fn get_voxel(&self, chunk_coords: Vector3Int, voxel_coords: VoxelCoords) -> Result<Voxel, VoxelAccessError>;
+    fn get_voxel_by_index(&self, chunk_coords: Vector3Int, voxel_index: usize) -> Result<Voxel, VoxelAccessError>;
+    fn set_voxel(&mut self, chunk_coords: Vector3Int, voxel_coords: VoxelCoords, data: Voxel) -> Result<Voxel, VoxelAccessError>;
+    fn set_voxel_by_index(&mut self, chunk_coords: Vector3Int, voxel_index: usize, data: Voxel) -> Result<Voxel, VoxelAccessError>;
+    fn get_world_voxel(&self, position: IVec3) -> Result<Voxel, VoxelAccessError>;
+    fn set_world_voxel(&mut self, position: IVec3, data: Voxel) -> Result<Voxel, VoxelAccessError>;
+}
+
+fn get_checked_index(voxel_coords: VoxelCoords) -> Result<usize, VoxelAccessError> {
+    if voxel_coords.x as i64 >= CHUNK_DIMENSIONS.x
+        || voxel_coords.y as i64 >= CHUNK_DIMENSIONS.y
+        || voxel_coords.z as i64 >= CHUNK_DIMENSIONS.z {
+        return Err(VoxelAccessError::OutOfBounds)
+    }
+
+    Ok(voxel_helpers::get_index_from_coords(voxel_coords))
+}
+
+impl ChunkState {
+    fn get_missing_chunk_error(&self, chunk_coords: Vector3Int) -> VoxelAccessError {
+        if self.generating.contains(&chunk_coords) {
+            VoxelAccessError::ChunkNotGenerated
+        } else {
+            VoxelAccessError::ChunkNotLoaded
+        }
+    }
 }
 
 impl ChunkLookup for ChunkState {
-    fn get_voxel(&self, chunk_coords: Vector3Int, voxel_coords: VoxelCoords) -> Option<Voxel> {
-        let index = voxel_helpers::get_index_from_coords(voxel_coords);
+    fn get_voxel(&self, chunk_coords: Vector3Int, voxel_coords: VoxelCoords) -> Result<Voxel, VoxelAccessError> {
+        let index = get_checked_index(voxel_coords)?;
         self.get_voxel_by_index(chunk_coords, index)
     }
 
-    fn get_voxel_by_index(&self, chunk_coords: Vector3Int, voxel_index: usize) -> Option<Voxel> {
-        if let Some(chunk) = self.chunks.get(&chunk_coords) {
-            if chunk.voxels.len() >= voxel_index {
-                return Some(chunk.voxels[voxel_index]);
-            }
+    fn get_voxel_by_index(&self, chunk_coords: Vector3Int, voxel_index: usize) -> Result<Voxel, VoxelAccessError> {
+        match self.chunks.get(&chunk_coords) {
+            Some(chunk) => chunk.voxels.get(voxel_index).ok_or(VoxelAccessError::OutOfBounds),
+            None => Err(self.get_missing_chunk_error(chunk_coords)),
         }
-
-        None
     }
 
-    fn set_voxel(&mut self, chunk_coords: Vector3Int, voxel_coords: VoxelCoords, data: Voxel) -> Option<Voxel> {
-        let index = voxel_helpers::get_index_from_coords(voxel_coords);
+    fn set_voxel(&mut self, chunk_coords: Vector3Int, voxel_coords: VoxelCoords, data: Voxel) -> Result<Voxel, VoxelAccessError> {
+        let index = get_checked_index(voxel_coords)?;
         self.set_voxel_by_index(chunk_coords, index, data)
     }
 
-    fn set_voxel_by_index(&mut self, chunk_coords: Vector3Int, voxel_index: usize, data: Voxel) -> Option<Voxel> {
+    fn set_voxel_by_index(&mut self, chunk_coords: Vector3Int, voxel_index: usize, data: Voxel) -> Result<Voxel, VoxelAccessError> {
         if let Some(chunk) = self.chunks.get_mut(&chunk_coords) {
-            if chunk.voxels.len() >= voxel_index {
-                chunk.voxels[voxel_index] = data;
-                return Some(data)
-            }
+            return chunk.voxels.set(voxel_index, data).ok_or(VoxelAccessError::OutOfBounds);
         }
 
-        None
+        Err(self.get_missing_chunk_error(chunk_coords))
     }
-}
-
-
-#[derive(Copy, Clone, Inspectable)]
-pub struct BiomeConfiguration {
 
-    #[inspectable(min = 0.0, max = 50.0)]
-    pub min_height: f64,
-
-    pub height_range: (f64, f64),
-    pub range: (f64, f64),
+    fn get_world_voxel(&self, position: IVec3) -> Result<Voxel, VoxelAccessError> {
+        let (chunk, local) = WorldPos(position).split();
+        self.get_voxel_by_index(chunk.0, local.index())
+    }
 
-    pub noise_config: Option::<NoiseConfiguration>,
+    fn set_world_voxel(&mut self, position: IVec3, data: Voxel) -> Result<Voxel, VoxelAccessError> {
+        let (chunk, local) = WorldPos(position).split();
+        self.set_voxel_by_index(chunk.0, local.index(), data)
+    }
 }
 
-#[derive(Copy, Clone, Inspectable)]
+
+#[derive(Copy, Clone, Inspectable, Deserialize)]
 pub struct NoiseConfiguration {
+    // derived from the world seed, see ConfigurationState::apply_world_seed
+    #[serde(default)]
     pub seed: i32,
 
     #[inspectable(min = 1, max = 8)]
@@ -176,83 +269,323 @@ pub struct NoiseConfiguration {
 
     #[inspectable(min = 0.00004, max = 1.0, speed=0.0001, wrapper=bigger_width)]
     pub freq: f64,
+
+    #[serde(default)]
+    pub mode: FractalMode,
+    #[serde(default = "default_lacunarity")]
+    #[inspectable(min = 1.0, max = 4.0)]
+    pub lacunarity: f64,
+    #[serde(default = "default_gain")]
+    #[inspectable(min = 0.0, max = 1.0)]
+    pub gain: f64,
+    // how far the sample point gets pushed around by the noise itself, 0 turns warping off
+    #[serde(default)]
+    #[inspectable(min = 0.0, max = 4.0)]
+    pub warp: f64,
+}
+
+fn default_lacunarity() -> f64 { 2.0 }
+fn default_gain() -> f64 { 0.5 }
+
+impl Default for NoiseConfiguration {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            octaves: 1,
+            freq: 0.01,
+            mode: FractalMode::Fbm,
+            lacunarity: default_lacunarity(),
+            gain: default_gain(),
+            warp: 0.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Inspectable)]
+pub struct TerrainDensityConfiguration {
+    pub enabled: bool,
+    pub noise_config: NoiseConfiguration,
+
+    // how many blocks the 3d noise can push the surface up or down
+    #[inspectable(min = 0.0, max = 64.0)]
+    pub amplitude: f64,
+    // below 1 stretches the noise vertically, which gives taller overhangs
+    #[inspectable(min = 0.1, max = 4.0)]
+    pub vertical_scale: f64,
 }
 
 #[derive(Copy, Clone, Inspectable)]
+pub struct FloatingIslandConfiguration {
+    pub enabled: bool,
+    pub noise_config: NoiseConfiguration,
+
+    // islands are centered on this height and taper off over thickness blocks above and below it
+    pub height: f64,
+    #[inspectable(min = 1.0, max = 64.0)]
+    pub thickness: f64,
+    // 3d noise above this becomes land, raising it gives fewer and smaller islands
+    #[inspectable(min = 0.0, max = 1.0)]
+    pub threshold: f64,
+}
+
+#[derive(Copy, Clone, Inspectable)]
+pub struct CaveConfiguration {
+    pub enabled: bool,
+
+    // noise caves are kept this many blocks under the surface, only entrance worms break through
+    #[inspectable(min = 0, max = 32)]
+    pub surface_margin: u8,
+    pub min_y: i64,
+
+    pub cheese_noise_config: NoiseConfiguration,
+    #[inspectable(min = 0.0, max = 1.0)]
+    pub cheese_threshold: f64,
+
+    pub spaghetti_noise_config: NoiseConfiguration,
+    pub spaghetti_second_noise_config: NoiseConfiguration,
+    // how far from the middle of both spaghetti noises still counts as tunnel
+    #[inspectable(min = 0.0, max = 0.5)]
+    pub spaghetti_width: f64,
+
+    pub worm_seed: i32,
+    #[inspectable(min = 0, max = 8)]
+    pub worms_per_chunk: u8,
+    pub worm_length: u16,
+    pub worm_step: f64,
+    pub worm_radius: (f64, f64),
+    // how many chunks away a worm can start and still reach this chunk, worm_length * worm_step should stay under this many chunks
+    #[inspectable(min = 0, max = 8)]
+    pub worm_range: u8,
+    #[inspectable(min = 0.0, max = 1.0)]
+    pub entrance_chance: f64,
+}
+
+#[derive(Clone, Inspectable)]
+pub struct RiverConfiguration {
+    pub enabled: bool,
+    pub noise_config: NoiseConfiguration,
+
+    // how close to the middle of the ridged noise counts as river, measured in ridge space (0..1)
+    #[inspectable(min = 0.0, max = 0.5)]
+    pub width: f64,
+    // the valley sides climb back up to the terrain over this much more of the ridge
+    #[inspectable(min = 0.0, max = 0.5)]
+    pub valley_width: f64,
+    pub channel_depth: f64,
+    // rivers never cut further than this below the terrain around them
+    pub max_valley_depth: f64,
+
+    #[inspectable(min = 0, max = 8)]
+    pub bed_depth: u8,
+    pub bed_blocks: Vec<LayerBlock>,
+}
+
+#[derive(Clone, Inspectable)]
 pub struct ConfigurationState {
+    // a number or any text, every seed below is derived from it
+    pub world_seed: String,
+
     pub height_noise_configuration: NoiseConfiguration,
-    pub height_noise_smooth_configuration: NoiseConfiguration,
 
     pub depth_adjust_noise_configuration: NoiseConfiguration,
+    // picks between the blocks in a biome layer
+    pub block_variant_noise_configuration: NoiseConfiguration,
+    pub terrain_density_configuration: TerrainDensityConfiguration,
+    pub floating_island_configuration: FloatingIslandConfiguration,
+    pub cave_configuration: CaveConfiguration,
+    pub river_configuration: RiverConfiguration,
+    pub ores: Vec<OreConfiguration>,
+    pub structures: Vec<StructureConfiguration>,
+    // decides between ocean and land, the climate noise picks the land biome
     pub biome_noise_configuration: NoiseConfiguration,
-    pub ocean_biome_config: BiomeConfiguration,
-    pub plains_biome_config: BiomeConfiguration,
-    pub mountains_biome_config: BiomeConfiguration,
+    pub temperature_noise_configuration: NoiseConfiguration,
+    pub humidity_noise_configuration: NoiseConfiguration,
+    pub biomes: Vec<BiomeConfiguration>,
+    // rows go from cold to hot and columns from dry to wet
+    pub climate_table: Vec<Vec<String>>,
+    pub ocean_biome: String,
+    pub ocean_threshold: f64,
 
     #[inspectable(min = 5, max = 200)]
     pub loading_distance: u8,
+    #[inspectable(min = 0, max = 16)]
+    pub vertical_loading_distance: u8,
     pub generate_ocean_water: bool,
+    pub meshing_mode: MeshingMode,
     pub biome_range: (f64, f64),
-    pub biome_smoothing: f64,
+    // spacing of the biome samples that get blended together, and how far away a sample still has an effect
+    #[inspectable(min = 1, max = 64)]
+    pub biome_grid_size: u8,
+    #[inspectable(min = 1.0, max = 128.0)]
+    pub biome_blend_radius: f64,
+
+    pub sea_level: f64,
+    // how close the biome noise has to be to the ocean threshold to use the ocean's shore block
+    pub shore_width: f64,
+    #[inspectable(min = 0, max = 128)]
+    pub shore_depth: u8,
 }
 
-impl Default for ConfigurationState  {
-    fn default() -> Self {
-        Self { 
+// the content files are checked against the block registry as they load, so it has to exist first
+impl FromWorld for ConfigurationState  {
+    fn from_world(world: &mut World) -> Self {
+        let registry = world.resource::<BlockRegistry>();
+        let biome_file = load_default_biomes(registry);
+
+        let mut config = Self { 
+            world_seed: "voxel adventure".to_string(),
             height_noise_configuration : NoiseConfiguration {
-                seed: 13459,
                 freq: 0.00250,
-                octaves: 8
+                octaves: 8,
+                ..default()
             },
             biome_noise_configuration: NoiseConfiguration {
-                seed: 5983,
                 freq: 0.00025,
                 octaves: 4,
+                ..default()
+            },
+            block_variant_noise_configuration: NoiseConfiguration {
+                freq: 0.02500,
+                octaves: 3,
+                gain: 0.35,
+                ..default()
             },
             depth_adjust_noise_configuration : NoiseConfiguration {
-                seed: 4958,
                 freq: 0.02125,
                 octaves: 6,
+                ..default()
             },
-            height_noise_smooth_configuration : NoiseConfiguration {
-                seed: 13459,
-                freq: 0.00825,
-                octaves: 3,
+            terrain_density_configuration: TerrainDensityConfiguration {
+                enabled: true,
+                noise_config: NoiseConfiguration {
+                    freq: 0.02500,
+                    octaves: 3,
+                    ..default()
+                },
+                amplitude: 8.0,
+                vertical_scale: 0.6,
             },
-            ocean_biome_config: BiomeConfiguration {
-                min_height: 5.0,
-                height_range: (0.0, 40.0),
-                range: (0.0, 0.3),
-                noise_config: Some(NoiseConfiguration {
-                    seed: 13459,
-                    freq: 0.00825,
+            floating_island_configuration: FloatingIslandConfiguration {
+                enabled: true,
+                noise_config: NoiseConfiguration {
+                    freq: 0.01500,
                     octaves: 3,
-                }),
+                    ..default()
+                },
+                height: 110.0,
+                thickness: 8.0,
+                threshold: 0.68,
             },
-            plains_biome_config: BiomeConfiguration {
-                min_height: 40.0,
-                height_range: (0.0, 15.0),
-                range: (0.3, 0.7),
-                noise_config: Some(NoiseConfiguration {
-                    seed: 13459,
-                    freq: 0.00825,
-                    octaves: 4,
-                }),
+            cave_configuration: CaveConfiguration {
+                enabled: true,
+                surface_margin: 6,
+                min_y: 2,
+                cheese_noise_config: NoiseConfiguration {
+                    freq: 0.01800,
+                    octaves: 2,
+                    ..default()
+                },
+                cheese_threshold: 0.78,
+                spaghetti_noise_config: NoiseConfiguration {
+                    freq: 0.01200,
+                    octaves: 2,
+                    ..default()
+                },
+                spaghetti_second_noise_config: NoiseConfiguration {
+                    freq: 0.01200,
+                    octaves: 2,
+                    ..default()
+                },
+                spaghetti_width: 0.03,
+                worm_seed: 0,
+                worms_per_chunk: 1,
+                worm_length: 48,
+                worm_step: 1.5,
+                worm_radius: (1.5, 3.0),
+                worm_range: 5,
+                entrance_chance: 0.15,
             },
-            mountains_biome_config: BiomeConfiguration {
-                min_height: 40.0,
-                height_range: (0.0, 100.0),
-                range: (0.7, 1.0),
-                noise_config: Some(NoiseConfiguration {
-                seed: 13459,
-                freq: 0.00250,
-                octaves: 8
-                }),
+            river_configuration: RiverConfiguration {
+                enabled: true,
+                noise_config: NoiseConfiguration {
+                    freq: 0.00150,
+                    octaves: 3,
+                    ..default()
+                },
+                width: 0.025,
+                valley_width: 0.06,
+                channel_depth: 4.0,
+                max_valley_depth: 12.0,
+                bed_depth: 2,
+                bed_blocks: vec![
+                    LayerBlock { max_variant: 0.5, block: "sand".to_string() },
+                    LayerBlock { max_variant: 1.0, block: "gravel".to_string() },
+                ],
+            },
+            ores: load_default_ores(registry),
+            structures: load_default_structures(registry),
+            temperature_noise_configuration: NoiseConfiguration {
+                freq: 0.00080,
+                octaves: 3,
+                ..default()
+            },
+            humidity_noise_configuration: NoiseConfiguration {
+                freq: 0.00080,
+                octaves: 3,
+                ..default()
             },
+            biomes: biome_file.biomes,
+            climate_table: biome_file.climate_table,
+            ocean_biome: biome_file.ocean_biome,
+            ocean_threshold: 0.3,
             loading_distance: 16,
+            vertical_loading_distance: 1,
             generate_ocean_water: false,
+            meshing_mode: MeshingMode::PerFace,
             biome_range: (0.0, 1.0),
-            biome_smoothing: 0.025,
+            biome_grid_size: 8,
+            biome_blend_radius: 24.0,
+            sea_level: 40.0,
+            shore_width: 0.01,
+            shore_depth: 30,
+        };
+
+        config.apply_world_seed();
+        config
+    }
+}
+
+impl ConfigurationState {
+    /// Derives every layer's seed from the world seed by hashing the layer's name, so the whole world
+    /// can be shared with the one value
+    pub fn apply_world_seed(&mut self) {
+        let world_seed = get_world_seed(&self.world_seed);
+        let seed = |layer: &str| derive_seed(world_seed, layer);
+
+        self.height_noise_configuration.seed = seed("height");
+        self.depth_adjust_noise_configuration.seed = seed("depth_adjust");
+        self.block_variant_noise_configuration.seed = seed("block_variant");
+        self.biome_noise_configuration.seed = seed("biome");
+        self.temperature_noise_configuration.seed = seed("temperature");
+        self.humidity_noise_configuration.seed = seed("humidity");
+        self.terrain_density_configuration.noise_config.seed = seed("terrain_density");
+        self.floating_island_configuration.noise_config.seed = seed("floating_islands");
+        self.cave_configuration.cheese_noise_config.seed = seed("caves/cheese");
+        self.cave_configuration.spaghetti_noise_config.seed = seed("caves/spaghetti");
+        self.cave_configuration.spaghetti_second_noise_config.seed = seed("caves/spaghetti_second");
+        self.cave_configuration.worm_seed = seed("caves/worms");
+        self.river_configuration.noise_config.seed = seed("rivers");
+
+        for biome in &mut self.biomes {
+            if let Some(noise_config) = &mut biome.noise_config {
+                noise_config.seed = seed(&format!("biomes/{}", biome.name));
+            }
+        }
+        for ore in &mut self.ores {
+            ore.seed = seed(&format!("ores/{}", ore.block));
+        }
+        for structure in &mut self.structures {
+            structure.seed = seed(&format!("structures/{}", structure.name));
         }
     }
 }
@@ -281,23 +614,15 @@ pub fn reload_chunk(
 
     state.chunks.clear();
     state.chunks_load.clear();
+    state.generating.clear();
 
-    let copy_center = state.center.clone();
-    let min = 0 - generation_state.loading_distance as i64;
-    let max = generation_state.loading_distance as i64;
-
-    for x in min..max {
-        for z in min..max {
-            state.chunks_load.push(Vector3Int { x: x, y: 0, z: z } + copy_center);
-        }
-    }
-
+    queue_chunks_in_range(&mut state, &generation_state);
 }
 
 pub struct SetBlockTypeEvent {
     index: usize,
     chunk_coords: Vector3Int,
-    block_type: BlockType,
+    block_type: BlockId,
     flow_rate: u8,
     replace: bool,
 }
@@ -310,33 +635,42 @@ pub fn spawn_random_blocks(
     if !input.just_pressed(KeyCode::I) { return }
     for (&coords, _) in state.chunks.iter() {
         if coords.x % 2 == 0 && coords.z % 2 == 0 { continue }
-        writer.send(FluidUpdateEvent(coords, VoxelCoords {x: 6, y : 100, z: 6}, 8));
+        let position = WorldPos::from_parts(ChunkPos(coords), LocalPos(VoxelCoords {x: 6, y : CHUNK_HEIGHT as u16 - 28, z: 6}));
+        writer.send(FluidUpdateEvent(position, 8));
     }
 }
 
 pub fn handle_set_block_type_events(
     mut reader: EventReader<SetBlockTypeEvent>,
     mut state: ResMut<ChunkState>,
+    registry: Res<BlockRegistry>,
     mut commands: Commands,
 ) {
     let mut changes = HashSet::<Vector3Int>::new();
 
     for event in reader.iter() {
 
-        if let Some(voxel) = state.get_voxel_by_index(event.chunk_coords, event.index) {
-            if event.replace == false && voxel_helpers::is_filled(voxel) {
+        if let Ok(voxel) = state.get_voxel_by_index(event.chunk_coords, event.index) {
+            if event.replace == false && voxel.is_filled() {
                 continue 
             }
 
-            let mut updated = voxel_helpers::set_block_type(voxel, event.block_type);
-            if event.block_type == BlockType::Water {
+            let mut updated = voxel;
+            // the old block's facing or fluid level doesn't carry over to a different block
+            if !voxel.is_filled() || voxel.block_type() != event.block_type {
+                updated.set_block_state(0);
+            }
+            updated.set_block_type(event.block_type);
+            if registry.is_fluid(event.block_type) {
+                updated.set_fluid_level(event.flow_rate);
                 if let Some(chunk_data) = state.chunks.get_mut(&event.chunk_coords) {
-                    chunk_data.flowing_fluids.insert_unique_unchecked(event.index, event.flow_rate);
+                    chunk_data.fluid_updates.insert(event.index);
                 }
             }
-            updated = voxel_helpers::set_filled(updated, true);
-            state.set_voxel_by_index(event.chunk_coords, event.index, updated);
-            changes.insert_unique_unchecked(event.chunk_coords);
+            updated.set_filled(true);
+            if state.set_voxel_by_index(event.chunk_coords, event.index, updated).is_ok() {
+                changes.insert(event.chunk_coords);
+            }
         }
     }
 
@@ -359,6 +693,7 @@ pub fn queue_new_chunks(
         let next_coord = state.chunks_load.pop();
         match next_coord {
             Some(v) => {
+                state.generating.insert(v);
                 spawn_new_chunk(&mut commands, v);
             },
             None => break,
@@ -377,7 +712,7 @@ pub fn manage_loaded_chunk(
     let mut camera_coords: Option::<Vector3Int> = None;
 
     for (_, transform) in camera_query.iter() {
-        camera_coords = Some(Vector3Int {x: transform.translation.x as i64 / 16, y: transform.translation.y as i64, z: transform.translation.z as i64 / 16 });
+        camera_coords = Some(WorldPos::from_translation(transform.translation).chunk().0);
     }
 
     // do some stuff to despawn old chunks (or at least de_render)
@@ -385,71 +720,262 @@ pub fn manage_loaded_chunk(
     // update any chunks with appropriate flags based on location
     match camera_coords {
         Some(camera_coords) => {
-            let offset_vector = Vector3Int {x: config.loading_distance as i64, y: 0, z: config.loading_distance as i64};
-            let max = camera_coords + offset_vector;
-            let min = camera_coords - offset_vector;
-            state.center = camera_coords;
             // set some chunks to be loaded
-            for x in min.x..max.x {
-                for z in min.z..max.z {
-                    let coords = Vector3Int {x:x, y: 0, z: z};
-                    if !state.chunks.contains_key(&coords)
-                        && !state.chunks_load.contains(&coords) {
-                            state.chunks_load.push(coords);
-                    }
-                }
+            if state.center != camera_coords {
+                state.center = camera_coords;
+                queue_chunks_in_range(&mut state, &config);
             }
 
-            let unload_distance = Vector3Int {x: config.loading_distance as i64 + 4, y: 0, z: config.loading_distance as i64 + 4};
-            let unload_min = camera_coords - unload_distance;
-            let unload_max = camera_coords + unload_distance;
-			// TODO: Unloading doesn't seem to work :(
-            for (e, chunk) in &query {
-                if chunk.coords.x < unload_min.x
-                    || chunk.coords.z < unload_min.z
-                    || chunk.coords.x > unload_max.x
-                    || chunk.coords.z > unload_max.z {
-
+            // a little further out than they load, so chunks on the edge don't flicker in and out
+            let unload_distance = config.loading_distance as i64 + 4;
+            let vertical_unload_distance = config.vertical_loading_distance as i64 + 2;
+            let in_unload_range = |coords: Vector3Int| is_chunk_in_range(coords, camera_coords, unload_distance, vertical_unload_distance);
+            let in_load_range = |coords: Vector3Int| {
+                is_chunk_in_range(coords, camera_coords, config.loading_distance as i64, config.vertical_loading_distance as i64)
+            };
 
-                        state.chunks.remove(&chunk.coords);
-                        commands.entity(e).despawn_recursive();
-                    }
+            for (e, chunk) in &query {
+                // chunks that haven't finished generating aren't worth keeping past the load range,
+                // despawning them drops their task which cancels it
+                let still_generating = state.generating.contains(&chunk.coords);
+                if !in_unload_range(chunk.coords) || (still_generating && !in_load_range(chunk.coords)) {
+                    state.chunks.remove(&chunk.coords);
+                    state.generating.remove(&chunk.coords);
+                    commands.entity(e).despawn_recursive();
+                }
             }
+
         },
         None => return
     }
 
 }
 
+// seeds can be changed from the inspector, so the generators are rebuilt whenever the config changes
+pub fn update_noise_sources(
+    mut config: ResMut<ConfigurationState>,
+    mut sources: ResMut<NoiseSources>,
+    mut last_world_seed: Local<Option<String>>,
+) {
+    if !config.is_changed() { return }
+
+    if last_world_seed.as_deref() != Some(config.world_seed.as_str()) {
+        config.apply_world_seed();
+        *last_world_seed = Some(config.world_seed.clone());
+    }
+
+    *sources = NoiseSources::from_config(&config);
+}
+
+// block and biome names are looked up again whenever the config changes. while the config names one that
+// doesn't exist the resource is removed, and generation waits for the name to be fixed
+pub fn update_resolved_configuration(
+    mut commands: Commands,
+    config: Res<ConfigurationState>,
+    registry: Res<BlockRegistry>,
+) {
+    if !config.is_changed() { return }
+
+    match ResolvedConfiguration::new(&config, &registry) {
+        Ok(resolved) => commands.insert_resource(resolved),
+        Err(err) => {
+            error!("pausing generation, the configuration is invalid: {:?}", err);
+            commands.remove_resource::<ResolvedConfiguration>();
+        },
+    }
+}
+
+// everything a generation task reads, copied out of the resources so the task doesn't hold on to the world.
+// the copy is shared by every task and only made again when one of the resources changes
+pub struct GenerationContext {
+    config: ConfigurationState,
+    resolved: ResolvedConfiguration,
+    sources: NoiseSources,
+    registry: BlockRegistry,
+}
+
+// dropping the task cancels it, so a chunk that's despawned before it finishes stops generating
+#[derive(Component)]
+pub struct GenerationTask(Task<(VoxelStorage, Vec<StructureBlock>)>);
+
 pub fn generator(
-    mut state: ResMut<ChunkState>,
     config: Res<ConfigurationState>,
+    resolved: Option<Res<ResolvedConfiguration>>,
+    sources: Res<NoiseSources>,
+    registry: Res<BlockRegistry>,
+    mut context: Local<Option<Arc<GenerationContext>>>,
+    mut commands: Commands,
+    query: Query<(Entity, &Chunk), With<Generate>>,
+) {
+    let resolved = match resolved {
+        Some(resolved) => resolved,
+        None => return,
+    };
+
+    // checked before bailing out on an empty query, otherwise a change made while nothing was queued is missed
+    let changed = config.is_changed() || resolved.is_changed() || sources.is_changed() || registry.is_changed();
+    if changed || context.is_none() {
+        *context = Some(Arc::new(GenerationContext {
+            config: config.clone(),
+            resolved: resolved.clone(),
+            sources: sources.clone(),
+            registry: registry.clone(),
+        }));
+    }
+
+    if query.is_empty() { return }
+    let context = match context.as_ref() {
+        Some(context) => context,
+        None => return,
+    };
+    let pool = AsyncComputeTaskPool::get();
+
+    for (entity, chunk) in query.iter() {
+        let context = context.clone();
+        let coords = chunk.coords;
+        let task = pool.spawn(async move {
+            let voxels = chunks::generate_chunk(coords, &context.config, &context.resolved, &context.sources, &context.registry);
+            // placed from the untouched terrain, so where they go doesn't depend on which neighbours were built first
+            let structure_blocks = structures::get_structure_blocks(coords, &voxels, &context.resolved.structures);
+            (voxels, structure_blocks)
+        });
+
+        commands.entity(entity).remove::<Generate>().insert(GenerationTask(task));
+    }
+}
+
+pub fn poll_generation_tasks(
+    mut state: ResMut<ChunkState>,
     mut commands: Commands,
-    mut query: Query<(Entity, &Chunk), With<Generate>>,
+    mut query: Query<(Entity, &Chunk, &mut GenerationTask)>,
 ) {
-    for (entity, chunk) in query.iter_mut() {
-        let mut new_chunk_data = ChunkData { 
-            voxels: chunks::get_height_map(Vector3{x: chunk.coords.x as f64, y: chunk.coords.y as f64, z: chunk.coords.z as f64}, config.clone()),
-            entity: Some(entity.clone()),
+    let mut changes = HashSet::<Vector3Int>::new();
+
+    for (entity, chunk, mut task) in query.iter_mut() {
+        let (voxels, structure_blocks) = match future::block_on(future::poll_once(&mut task.0)) {
+            Some(result) => result,
+            None => continue,
+        };
+
+        let new_chunk_data = ChunkData { 
+            voxels,
+            entity: Some(entity),
+            structure_blocks,
             ..default()
         };
-        let fluid_map = update_initial_fluids(&new_chunk_data.voxels);
-        new_chunk_data.flowing_fluids = fluid_map;
-        state.chunks.insert_unique_unchecked(chunk.coords, new_chunk_data);
-        commands.entity(entity).remove::<Generate>();
+        state.generating.remove(&chunk.coords);
+        state.chunks.insert(chunk.coords, new_chunk_data);
+        commands.entity(entity).remove::<GenerationTask>();
+
+        // neighbours that already have their structures didn't wait for this chunk, usually because it was
+        // outside the vertical loading distance at the time, so its blocks go straight in
+        for neighbour in get_structure_neighbourhood(chunk.coords) {
+            let missing = state.chunks.get(&neighbour).is_some_and(|chunk_data| {
+                chunk_data.has_generated_structures && !chunk_data.applied_structures.contains(&chunk.coords)
+            });
+            if missing {
+                apply_structure_blocks(&mut state, chunk.coords, neighbour, &mut changes);
+            }
+        }
     }
+
+    mark_chunks_for_render(&state, &mut commands, changes);
+}
+
+// the chunk and every chunk around it, structures never reach further than one chunk over
+fn get_structure_neighbourhood(coords: Vector3Int) -> impl Iterator<Item = Vector3Int> {
+    (-1..=1).flat_map(move |x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| coords + Vector3Int { x, y, z })))
+}
+
+// copies the blocks that source's structures place in target into target, and notes every chunk that
+// needs meshing again, including the ones across the border from a changed voxel
+fn apply_structure_blocks(state: &mut ChunkState, source: Vector3Int, target: Vector3Int, changes: &mut HashSet<Vector3Int>) {
+    let blocks: Vec<StructureBlock> = match state.chunks.get(&source) {
+        Some(chunk_data) => chunk_data.structure_blocks.iter().filter(|block| block.position.chunk().0 == target).copied().collect(),
+        None => return,
+    };
+    let target_data = match state.chunks.get_mut(&target) {
+        Some(chunk_data) => chunk_data,
+        None => return,
+    };
+
+    target_data.applied_structures.insert(source);
+    for block in blocks {
+        let index = block.position.local().index();
+        let mut voxel = match target_data.voxels.get(index) {
+            Some(voxel) => voxel,
+            None => continue,
+        };
+
+        let before = voxel;
+        block.apply(&mut voxel);
+        if voxel == before { continue }
+
+        target_data.voxels.set(index, voxel);
+        changes.insert(target);
+        for face in FACES {
+            changes.insert((block.position + get_face_direction(face)).chunk().0);
+        }
+    }
+}
+
+fn mark_chunks_for_render(state: &ChunkState, commands: &mut Commands, changes: HashSet<Vector3Int>) {
+    for coords in changes {
+        if let Some(entity) = state.chunks.get(&coords).and_then(|chunk_data| chunk_data.entity) {
+            commands.entity(entity).insert(NeedsRender);
+        }
+    }
+}
+
+// a chunk's structures go in once every neighbour inside the loading range is generated, then its own and its neighbours' structure
+// blocks are applied in a fixed order. the result only depends on the terrain, not on which chunk loaded first
+pub fn generate_structures(
+    mut state: ResMut<ChunkState>,
+    config: Res<ConfigurationState>,
+    mut commands: Commands,
+) {
+    let ready: Vec<Vector3Int> = state.chunks.iter()
+        .filter(|(_, chunk_data)| !chunk_data.has_generated_structures)
+        .map(|(&coords, _)| coords)
+        .filter(|&coords| get_structure_neighbourhood(coords).all(|neighbour| !is_neighbour_missing(&state, &config, neighbour)))
+        .collect();
+
+    let mut changes = HashSet::<Vector3Int>::new();
+    for coords in ready {
+        let mut sources: Vec<Vector3Int> = get_structure_neighbourhood(coords)
+            .filter(|source| state.chunks.contains_key(source))
+            .collect();
+        sources.sort_by_key(|source| (source.x, source.y, source.z));
+
+        for source in sources {
+            apply_structure_blocks(&mut state, source, coords, &mut changes);
+        }
+
+        if let Some(chunk_data) = state.chunks.get_mut(&coords) {
+            chunk_data.has_generated_structures = true;
+        }
+    }
+
+    mark_chunks_for_render(&state, &mut commands, changes);
 }
 
 pub fn generate_full_edge_meshes (
     mut commands: Commands,
     mut query : Query<(Entity, &Chunk), (With<GenerateFaces>, Without<Generate>)>,
-    mut state: ResMut<ChunkState>
+    mut state: ResMut<ChunkState>,
+    config: Res<ConfigurationState>,
 ) {
     for (e, chunk) in query.iter_mut() {
         let left        = chunk.coords + Vector3Int { x:  0, y: 0, z:  1 };
         let right       = chunk.coords + Vector3Int { x:  0, y: 0, z: -1 };
         let forward     = chunk.coords + Vector3Int { x:  1, y: 0, z:  0 };
         let backward    = chunk.coords + Vector3Int { x: -1, y: 0, z:  0 };
+        let up          = chunk.coords + Vector3Int { x:  0, y: 1, z:  0 };
+        let down        = chunk.coords + Vector3Int { x:  0, y:-1, z:  0 };
+
+        // the top and bottom layers of loaded chunks don't have anything to wait for above or below them
+        let has_vertical_neighbours = [up, down].iter().all(|&coords| !is_neighbour_missing(&state, &config, coords));
+        if !has_vertical_neighbours { continue }
 
         let mut_state = &mut state;
         if let Some(_) = mut_state.chunks.get_many_mut([&left, &right, &forward, &backward, &chunk.coords]) {
@@ -460,18 +986,93 @@ pub fn generate_full_edge_meshes (
     }
 }
 
+// the two meshers need different materials, so switching throws away every chunk's mesh entity and renders it again
+pub fn apply_meshing_mode(
+    mut commands: Commands,
+    config: Res<ConfigurationState>,
+    mut last_mode: Local<Option<MeshingMode>>,
+    // chunks still waiting on GenerateFaces haven't been meshed yet, they'll pick up the new mode when they are
+    query: Query<Entity, (With<MeshReference>, Without<GenerateFaces>)>,
+) {
+    let mode = config.meshing_mode;
+    if last_mode.replace(mode).is_none_or(|last_mode| last_mode == mode) { return }
+
+    for entity in query.iter() {
+        commands.entity(entity).despawn_descendants();
+        commands.entity(entity).insert(MeshReference::default()).insert(NeedsRender);
+    }
+}
+
+// what a meshing task reads besides the chunk snapshot
+struct MeshingContext {
+    mode: MeshingMode,
+    cube_meshes: CubeMeshData,
+    registry: BlockRegistry,
+}
+
+// replacing the task drops the old one, so only the newest snapshot of a chunk finishes meshing.
+// the mode is kept with it since the mesh has to go on the material its mesher was built for
+#[derive(Component)]
+pub struct MeshingTask {
+    task: Task<MeshData>,
+    mode: MeshingMode,
+}
+
 pub fn render_chunk(
-    material_cache: Res<MaterialCache>,
     cube_meshes: Res<CubeMeshData>,
+    registry: Res<BlockRegistry>,
+    config: Res<ConfigurationState>,
     state: Res<ChunkState>,
     mut commands: Commands,
+    query: Query<(Entity, &Chunk), With<NeedsRender>>,
+) {
+    if query.is_empty() { return }
+
+    let context = Arc::new(MeshingContext {
+        mode: config.meshing_mode,
+        cube_meshes: cube_meshes.clone(),
+        registry: registry.clone(),
+    });
+    let pool = AsyncComputeTaskPool::get();
+
+    for (entity, chunk) in query.iter() {
+
+        // a neighbor is still generating, leave NeedsRender in place and try again next frame
+        let snapshot = match ChunkSnapshot::new(chunk.coords, &state) {
+            Ok(snapshot) => snapshot,
+            Err(_) => continue,
+        };
+
+        let mode = context.mode;
+        let context = context.clone();
+        let task = pool.spawn(async move {
+            build_chunk_mesh(&snapshot, context.mode, &context.cube_meshes, &context.registry)
+        });
+
+        commands.entity(entity).remove::<NeedsRender>().insert(MeshingTask { task, mode });
+    }
+}
+
+pub fn upload_chunk_meshes(
+    material_cache: Res<MaterialCache>,
+    config: Res<ConfigurationState>,
+    mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    query: Query<(Entity, &Chunk, &MeshReference), With<NeedsRender>>,
+    mut query: Query<(Entity, &Chunk, &MeshReference, &mut MeshingTask)>,
 ) {
-    for (entity, chunk, mesh_reference) in query.iter() {
+    for (entity, chunk, mesh_reference, mut task) in query.iter_mut() {
+        let mesh_data = match future::block_on(future::poll_once(&mut task.task)) {
+            Some(mesh_data) => mesh_data,
+            None => continue,
+        };
 
-        let face_data = generate_mesh_raw_data(chunk.coords, &state);
-        let mesh_data = get_mesh_data(&face_data, &cube_meshes);
+        // the mode was switched while this was meshing, its mesh would end up on the wrong material
+        if task.mode != config.meshing_mode {
+            commands.entity(entity).remove::<MeshingTask>().insert(NeedsRender);
+            continue;
+        }
+
+        let mode = task.mode;
         let indices = mesh::Indices::U32(mesh_data.indicies);
 
         let chunk_mesh_handle = match mesh_reference.handle.clone() {
@@ -479,21 +1080,36 @@ pub fn render_chunk(
             None => { 
                 let handle = meshes.add(mesh::Mesh::new(mesh::PrimitiveTopology::TriangleList));
 
-                let chunk_material;
-
-                match &material_cache.chunk_material {
-                    Some(material) => chunk_material = material.clone(),
-                    None => panic!("no chunk mesh material set")
-                }
-
-                let mesh_id = commands.spawn_bundle(PbrBundle {
-                    mesh: handle.clone(),
-                    material: chunk_material,
-                    ..default()
-                }).id();
+                // greedy meshes need the chunk material to repeat their tiles
+                let mesh_id = match mode {
+                    MeshingMode::PerFace => {
+                        let chunk_material = match &material_cache.chunk_material {
+                            Some(material) => material.clone(),
+                            None => panic!("no chunk mesh material set")
+                        };
+
+                        commands.spawn_bundle(PbrBundle {
+                            mesh: handle.clone(),
+                            material: chunk_material,
+                            ..default()
+                        }).id()
+                    },
+                    MeshingMode::Greedy => {
+                        let chunk_material = match &material_cache.greedy_chunk_material {
+                            Some(material) => material.clone(),
+                            None => panic!("no greedy chunk mesh material set")
+                        };
+
+                        commands.spawn_bundle(MaterialMeshBundle {
+                            mesh: handle.clone(),
+                            material: chunk_material,
+                            ..default()
+                        }).id()
+                    },
+                };
 
                 let sb = SpatialBundle {
-                    transform: Transform::from_xyz(chunk.coords.x as f32 * 16.0, 0.0, chunk.coords.z as f32 * 16.0),
+                    transform: Transform::from_translation(ChunkPos(chunk.coords).origin().translation()),
                     ..default()
                 };
 
@@ -511,15 +1127,18 @@ pub fn render_chunk(
         chunk_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, mesh_data.verticies);
         chunk_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, mesh_data.normals);
         chunk_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, mesh_data.uvs);
+        if mode == MeshingMode::Greedy {
+            chunk_mesh.insert_attribute(ATTRIBUTE_ATLAS_TILE, mesh_data.tiles);
+        }
 
 
-        commands.entity(entity).remove::<NeedsRender>();
+        commands.entity(entity).remove::<MeshingTask>();
     }
 
 }
 
 #[allow(dead_code)]
-fn copy_chunk_side(voxels: &VoxelCollection, out_voxels: &mut [Voxel;16*128], indicies: &[usize;16*128]) {
+fn copy_chunk_side(voxels: &VoxelCollection, out_voxels: &mut [Voxel;CHUNK_WIDTH*CHUNK_HEIGHT], indicies: &[usize;CHUNK_WIDTH*CHUNK_HEIGHT]) {
     let mut out_index  = 0;
     	for i in 0..indicies.len() {
         out_voxels[out_index] = voxels[i];
@@ -533,7 +1152,7 @@ pub fn spawn_new_chunk(commands: &mut Commands, coords: Vector3Int) {
             coords: coords,
             render: false,
         },
-        Transform::from_xyz((coords.x * 16) as f32, (coords.y * 128) as f32, (coords.z * 16) as f32),
+        Transform::from_translation(ChunkPos(coords).origin().translation()),
         Generate,
         GenerateFaces,
         MeshReference {handle : None }
@@ -545,149 +1164,153 @@ pub struct FluidUpdateResult {
     pub updates: Vec<(Vector3Int, VoxelCoords, u8)>,
 }
 
-pub fn update_initial_fluids(voxels: &VoxelCollection) -> HashMap<usize, u8> {
-    let mut fluid_map = HashMap::<usize, u8>::new();
-    for i in 0..voxels.len() {
-        let voxel = voxels[i];
-        if voxel_helpers::get_block_type(voxel) == BlockType::Water as u64 {
-            fluid_map.insert_unique_unchecked(i, 0);
-        }
-    }
-
-    fluid_map
-}
-
 pub fn fluid_update_system(
     mut fluid_event: EventWriter<FluidUpdateEvent>,
     query: Query<(Entity, &Chunk)>,
     mut chunk_state: ResMut<ChunkState>,
+    registry: Res<BlockRegistry>,
 ) {
     for (_, chunk) in query.iter() {
-        update_fluids(chunk.coords, &mut chunk_state, &mut fluid_event)
+        update_fluids(chunk.coords, &mut chunk_state, &registry, &mut fluid_event)
     }
 }
 
 pub fn fluid_update_event_processor(
     mut fluid_events: EventReader<FluidUpdateEvent>,
     mut set_block_writer: EventWriter<SetBlockTypeEvent>,
+    registry: Res<BlockRegistry>,
 ) {
+    let water = registry.id("water");
     for event in fluid_events.iter() {
-        let index = voxel_helpers::get_index_from_coords(event.1);
+        let (chunk, local) = event.0.split();
         set_block_writer.send(SetBlockTypeEvent{
-            index, 
-            chunk_coords: event.0,
-            block_type: BlockType::Water,
-            flow_rate: event.2, 
+            index: local.index(),
+            chunk_coords: chunk.0,
+            block_type: water,
+            flow_rate: event.1, 
             replace: false
         });
     }
 }
 
 // pub struct NeedsRenderEvent
-pub struct FluidUpdateEvent(Vector3Int, VoxelCoords, u8);
-
-pub fn update_fluids(chunk_coords: Vector3Int, chunk_state: &mut ChunkState, writer: &mut EventWriter<FluidUpdateEvent>) {
-
-    match chunk_state.chunks.get(&chunk_coords) {
-        Some(state) => {
-            let mut target_coords;
-            let mut target_chunk = chunk_coords;
-            let mut check_vec = vec!();
-
-            for (index, rate) in &state.flowing_fluids {
-                if rate > &0 {
-                    // check neighbors for voids and the push the update to results
-                    let coords = voxel_helpers::get_coords_as_voxel_coords(index.clone() as u64);
-                    target_coords = coords;
-                    if coords.y > 0 {
-                        target_coords.y -= 1;
-
-                        if let Some(voxel) = chunk_state.get_voxel(target_chunk, target_coords) {
-                            if !voxel_helpers::is_filled(voxel.clone()) {
-                                writer.send(FluidUpdateEvent(target_chunk, target_coords, 8));
-                                continue
-                            }
-
-                            if voxel_helpers::get_block_type(voxel.clone()) == BlockType::Water as u64 {
-                                continue  
-                            }
-                        }
-                    }
+pub struct FluidUpdateEvent(WorldPos, u8);
 
-                    target_chunk = chunk_coords;
-                    target_coords = coords;
+pub fn update_fluids(chunk_coords: Vector3Int, chunk_state: &mut ChunkState, registry: &BlockRegistry, writer: &mut EventWriter<FluidUpdateEvent>) {
+    let updates = match chunk_state.chunks.get_mut(&chunk_coords) {
+        Some(state) => std::mem::take(&mut state.fluid_updates),
+        None => return,
+    };
 
-                    // backward
-                    if coords.x == 0 {
-                        target_coords.x = 15;
-                        target_chunk = chunk_coords + VECTOR3_INT_BACKWARD;
-                    } else {
-                        target_coords.x -= 1;
-                    }
-                    check_vec.push((target_chunk, target_coords));
-
-                    target_chunk = chunk_coords;
-                    target_coords = coords;
-                    //forward
-                    if coords.x == 15 {
-                        target_coords.x = 0;
-                        target_chunk = chunk_coords + VECTOR3_INT_FORWARD;
-                    } else {
-                        target_coords.x += 1;
-                    }
+    // updates that touch a chunk which is still generating are kept around and retried
+    let mut deferred = vec![];
 
-                    check_vec.push((target_chunk, target_coords));
-                    target_chunk = chunk_coords;
-                    target_coords = coords;
-                    // left
-                    if coords.z == 0 {
-                        target_coords.z = 15;
-                        target_chunk = chunk_coords + VECTOR3_INT_RIGHT;
-                    } else {
-                        target_coords.z -= 1;
-                    }
+    for index in updates {
+        let position = WorldPos::from_parts(ChunkPos(chunk_coords), LocalPos::from_index(index));
+        let rate = match chunk_state.get_world_voxel(position.0) {
+            Ok(voxel) => voxel.fluid_level(),
+            Err(_) => 0,
+        };
 
-                    check_vec.push((target_chunk, target_coords));
-                    target_chunk = chunk_coords;
-                    target_coords = coords;
+        if rate == 0 { continue }
 
-                    //right
-                    if coords.z == 15 {
-                        target_coords.z = 0;
-                        target_chunk = chunk_coords + VECTOR3_INT_LEFT;
-                    } else {
-                        target_coords.z += 1;
-                    }
+        // fall straight down if there's room, otherwise spread out to the neighbors
+        let below = position + IVec3::NEG_Y;
+        match chunk_state.get_world_voxel(below.0) {
+            Ok(voxel) => {
+                if !voxel.is_filled() {
+                    writer.send(FluidUpdateEvent(below, 8));
+                    continue
+                }
 
-                    check_vec.push((target_chunk, target_coords));
-                    
+                if registry.is_fluid(voxel.block_type()) {
+                    continue
+                }
+            },
+            Err(VoxelAccessError::ChunkNotGenerated) => {
+                deferred.push(index);
+                continue
+            },
+            Err(_) => (),
+        }
 
-                    //iterate and check
-                    for i in 0..check_vec.len() {
-                        let (target_chunk, target_coords) = check_vec[i] ;
+        let mut waiting = false;
+        for offset in [IVec3::NEG_X, IVec3::X, IVec3::NEG_Z, IVec3::Z] {
+            let target = position + offset;
+            match chunk_state.get_world_voxel(target.0) {
+                Ok(voxel) => {
+                    if !voxel.is_filled() {
+                        writer.send(FluidUpdateEvent(target, rate - 1));
+                    }
+                },
+                Err(VoxelAccessError::ChunkNotGenerated) => waiting = true,
+                Err(_) => (),
+            }
+        }
 
-                        if let Some(voxel) = chunk_state.get_voxel(target_chunk, target_coords) {
-                            if !voxel_helpers::is_filled(voxel) {
-                                writer.send(FluidUpdateEvent(target_chunk, target_coords, rate - 1));
-                            }
+        if waiting { deferred.push(index); }
+    }
 
-                        }
+    if let Some(state) = chunk_state.chunks.get_mut(&chunk_coords) {
+        state.fluid_updates.extend(deferred);
+    }
+}
 
-                    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                }
+    fn state_with_chunk(coords: Vector3Int) -> ChunkState {
+        let mut state = ChunkState::default();
+        let voxels = VoxelStorage::from_voxels(&vec![Voxel::default(); CHUNK_VOLUME]);
+        state.chunks.insert(coords, ChunkData { voxels, ..default() });
+        state
+    }
 
-                check_vec.clear();
+    #[test]
+    fn last_index_is_in_bounds() {
+        let coords = Vector3Int { x: 0, y: 0, z: 0 };
+        let mut state = state_with_chunk(coords);
+        let mut filled = Voxel::default();
+        filled.set_filled(true);
 
-            }
+        assert!(state.get_voxel_by_index(coords, CHUNK_VOLUME - 1).is_ok());
+        assert!(state.set_voxel_by_index(coords, CHUNK_VOLUME - 1, filled).is_ok());
+        assert!(state.get_voxel_by_index(coords, CHUNK_VOLUME - 1).unwrap().is_filled());
 
-            if let Some(state) = chunk_state.chunks.get_mut(&chunk_coords) {
-                state.flowing_fluids.clear();
-            }
+        assert_eq!(state.get_voxel_by_index(coords, CHUNK_VOLUME), Err(VoxelAccessError::OutOfBounds));
+        assert_eq!(state.set_voxel_by_index(coords, CHUNK_VOLUME, filled), Err(VoxelAccessError::OutOfBounds));
+    }
 
-        },
-        None => ()
+    #[test]
+    fn coords_past_the_chunk_are_out_of_bounds() {
+        let coords = Vector3Int { x: 0, y: 0, z: 0 };
+        let mut state = state_with_chunk(coords);
+        let last = VoxelCoords { x: CHUNK_WIDTH as u16 - 1, y: CHUNK_HEIGHT as u16 - 1, z: CHUNK_WIDTH as u16 - 1 };
+
+        assert_eq!(state.get_voxel(coords, last).map(|voxel| voxel.index()), Ok(CHUNK_VOLUME - 1));
+        for outside in [
+            VoxelCoords { x: CHUNK_WIDTH as u16, ..last },
+            VoxelCoords { y: CHUNK_HEIGHT as u16, ..last },
+            VoxelCoords { z: CHUNK_WIDTH as u16, ..last },
+        ] {
+            assert_eq!(state.get_voxel(coords, outside), Err(VoxelAccessError::OutOfBounds));
+            assert_eq!(state.set_voxel(coords, outside, Voxel::default()), Err(VoxelAccessError::OutOfBounds));
+        }
     }
 
+    #[test]
+    fn missing_chunks_say_whether_they_are_generating() {
+        let coords = Vector3Int { x: 0, y: 0, z: 0 };
+        let generating = Vector3Int { x: 1, y: 0, z: 0 };
+        let mut state = state_with_chunk(coords);
+        state.generating.insert(generating);
+
+        // one voxel past either edge of the loaded chunk
+        assert_eq!(state.get_world_voxel(IVec3::new(CHUNK_WIDTH as i32, 0, 0)), Err(VoxelAccessError::ChunkNotGenerated));
+        assert_eq!(state.get_world_voxel(IVec3::new(-1, 0, 0)), Err(VoxelAccessError::ChunkNotLoaded));
+        assert_eq!(state.set_world_voxel(IVec3::new(CHUNK_WIDTH as i32, 0, 0), Voxel::default()), Err(VoxelAccessError::ChunkNotGenerated));
+        assert_eq!(state.set_world_voxel(IVec3::new(-1, 0, 0), Voxel::default()), Err(VoxelAccessError::ChunkNotLoaded));
+        assert!(state.get_world_voxel(IVec3::new(CHUNK_WIDTH as i32 - 1, 0, 0)).is_ok());
+    }
 }
-